@@ -0,0 +1,198 @@
+use similar::ChangeTag;
+use similar::TextDiff;
+
+use crate::repository::DiffLine;
+use crate::repository::DiffLineKind;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DiffSegment {
+    pub text: String,
+    /// Whether this part of the line was changed. Used to highlight the words that changed
+    /// between a deleted line and the added line that replaced it.
+    pub changed: bool,
+}
+
+pub struct DiffRow {
+    pub kind: DiffLineKind,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    pub segments: Vec<DiffSegment>,
+}
+
+impl DiffRow {
+    fn new(line: &DiffLine, segments: Vec<DiffSegment>) -> Self {
+        DiffRow {
+            kind: line.kind,
+            old_lineno: line.old_lineno,
+            new_lineno: line.new_lineno,
+            segments,
+        }
+    }
+
+    pub fn is_addition(&self) -> bool {
+        self.kind == DiffLineKind::Addition
+    }
+
+    pub fn is_deletion(&self) -> bool {
+        self.kind == DiffLineKind::Deletion
+    }
+}
+
+fn push_segment(segments: &mut Vec<DiffSegment>, text: &str, changed: bool) {
+    match segments.last_mut() {
+        Some(last) if last.changed == changed => last.text.push_str(text),
+        _ => segments.push(DiffSegment {
+            text: text.to_owned(),
+            changed,
+        }),
+    }
+}
+
+/// Splits a pair of lines into segments, marking the words that differ between them.
+fn word_diff(old: &str, new: &str) -> (Vec<DiffSegment>, Vec<DiffSegment>) {
+    let mut old_segments = Vec::new();
+    let mut new_segments = Vec::new();
+    let diff = TextDiff::from_words(old, new);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                push_segment(&mut old_segments, change.value(), false);
+                push_segment(&mut new_segments, change.value(), false);
+            }
+            ChangeTag::Delete => push_segment(&mut old_segments, change.value(), true),
+            ChangeTag::Insert => push_segment(&mut new_segments, change.value(), true),
+        }
+    }
+    (old_segments, new_segments)
+}
+
+fn unchanged(line: &DiffLine) -> Vec<DiffSegment> {
+    vec![DiffSegment {
+        text: line.content.clone(),
+        changed: false,
+    }]
+}
+
+/// Converts the lines of a hunk into rows for display. When a run of deleted lines is
+/// immediately followed by a run of added lines, the lines are paired up and the words that
+/// changed within each pair are highlighted.
+pub fn highlight_words(lines: &[DiffLine]) -> Vec<DiffRow> {
+    let mut rows = Vec::with_capacity(lines.len());
+    let mut ndx = 0;
+    while ndx < lines.len() {
+        if lines[ndx].kind != DiffLineKind::Deletion {
+            rows.push(DiffRow::new(&lines[ndx], unchanged(&lines[ndx])));
+            ndx += 1;
+            continue;
+        }
+
+        let deletions_end = lines[ndx..]
+            .iter()
+            .position(|l| l.kind != DiffLineKind::Deletion)
+            .map_or(lines.len(), |pos| ndx + pos);
+        let additions_end = lines[deletions_end..]
+            .iter()
+            .position(|l| l.kind != DiffLineKind::Addition)
+            .map_or(lines.len(), |pos| deletions_end + pos);
+        let deletions = &lines[ndx..deletions_end];
+        let additions = &lines[deletions_end..additions_end];
+
+        let mut old_rows = Vec::with_capacity(deletions.len());
+        let mut new_rows = Vec::with_capacity(additions.len());
+        for (ndx, old) in deletions.iter().enumerate() {
+            match additions.get(ndx) {
+                Some(new) => {
+                    let (old_segments, new_segments) = word_diff(&old.content, &new.content);
+                    old_rows.push(DiffRow::new(old, old_segments));
+                    new_rows.push(DiffRow::new(new, new_segments));
+                }
+                None => old_rows.push(DiffRow::new(old, unchanged(old))),
+            }
+        }
+        for new in additions.iter().skip(deletions.len()) {
+            new_rows.push(DiffRow::new(new, unchanged(new)));
+        }
+        rows.extend(old_rows);
+        rows.extend(new_rows);
+
+        ndx = additions_end;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(text: &str, changed: bool) -> DiffSegment {
+        DiffSegment {
+            text: text.to_owned(),
+            changed,
+        }
+    }
+
+    fn line(kind: DiffLineKind, content: &str) -> DiffLine {
+        DiffLine {
+            kind,
+            old_lineno: None,
+            new_lineno: None,
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_word_diff() {
+        let (old, new) = word_diff("the quick brown fox", "the slow brown fox");
+        assert_eq!(
+            vec![
+                segment("the ", false),
+                segment("quick", true),
+                segment(" brown fox", false)
+            ],
+            old
+        );
+        assert_eq!(
+            vec![
+                segment("the ", false),
+                segment("slow", true),
+                segment(" brown fox", false)
+            ],
+            new
+        );
+    }
+
+    #[test]
+    fn test_highlight_words_pairs_lines() {
+        let lines = vec![
+            line(DiffLineKind::Context, "context"),
+            line(DiffLineKind::Deletion, "one two"),
+            line(DiffLineKind::Deletion, "removed"),
+            line(DiffLineKind::Addition, "one three"),
+            line(DiffLineKind::Context, "context"),
+            line(DiffLineKind::Addition, "added"),
+        ];
+        let rows = highlight_words(&lines);
+        let kinds: Vec<DiffLineKind> = rows.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            vec![
+                DiffLineKind::Context,
+                DiffLineKind::Deletion,
+                DiffLineKind::Deletion,
+                DiffLineKind::Addition,
+                DiffLineKind::Context,
+                DiffLineKind::Addition,
+            ],
+            kinds
+        );
+        assert_eq!(
+            vec![segment("one ", false), segment("two", true)],
+            rows[1].segments
+        );
+        assert_eq!(vec![segment("removed", false)], rows[2].segments);
+        assert_eq!(
+            vec![segment("one ", false), segment("three", true)],
+            rows[3].segments
+        );
+        assert_eq!(vec![segment("added", false)], rows[5].segments);
+    }
+}
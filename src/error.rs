@@ -2,8 +2,6 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum MyError {
-    #[error("Bare Git repos are not yet supported.")]
-    BareGitRepo,
     #[error("This is not valid Wiki folder: {path}")]
     GitRepoDoesNotExist { path: std::path::PathBuf },
     #[error("Failed to open Git repo: {err}")]
@@ -15,6 +13,8 @@ pub enum MyError {
     },
     #[error("Path is not valid.")]
     InvalidPath,
+    #[error("A file or directory already exists at that path.")]
+    PathAlreadyExists,
     #[error("io error")]
     Io {
         #[from]
@@ -37,6 +37,11 @@ pub enum MyError {
         #[from]
         source: std::str::Utf8Error,
     },
+    #[error("Failed to watch for changes: {source}")]
+    Watch {
+        #[from]
+        source: notify::Error,
+    },
     #[error("Search indexer failed in some way")]
     SearchIndex {
         #[from]
@@ -49,4 +54,24 @@ pub enum MyError {
     },
     #[error("Cross-site request forgery detected")]
     Csrf,
+    #[error("This operation is not supported by this type of repository.")]
+    UnsupportedOperation,
+    #[error("No commit author is configured.")]
+    NoCommitAuthor,
+    #[error("The commit author needs a name and an email address without angle brackets.")]
+    InvalidAuthor,
+    #[error("Syncing is not configured in smeagol.toml.")]
+    SyncNotConfigured,
+    #[error("The wiki is not being served from a branch.")]
+    NotOnBranch,
+    #[error("The remote rejected the push: {message}")]
+    PushRejected { message: String },
+    #[error("Branch not found: {branch}")]
+    BranchNotFound { branch: String },
+    #[error("Revision not found: {revision}")]
+    RevisionNotFound { revision: String },
+    #[error("The branch moved on while the change was being saved.")]
+    HeadMoved,
+    #[error("Commit has a time that cannot be represented.")]
+    InvalidCommitTime,
 }
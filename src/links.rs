@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+use rocket::http::RawStr;
+
+use crate::page::is_page;
+use crate::page::markdown_options;
+
+/// Where a link points and where its destination appears in the Markdown source.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LinkDestination {
+    pub range: Range<usize>,
+    pub dest: String,
+}
+
+/// Finds the destinations of inline links, images, and link reference definitions.
+///
+/// Destinations that cannot be found verbatim in the source (for example because they contain
+/// escape sequences) are skipped.
+pub fn find_link_destinations(src: &str) -> Vec<LinkDestination> {
+    let mut ret = Vec::new();
+    let parser = Parser::new_ext(src, markdown_options()).into_offset_iter();
+
+    for (_, def) in parser.reference_definitions().iter() {
+        let text = &src[def.span.clone()];
+        let Some(colon) = text.find("]:") else {
+            continue;
+        };
+        let start = def.span.start + colon + 2;
+        if let Some(pos) = src[start..def.span.end].find(def.dest.as_ref()) {
+            ret.push(LinkDestination {
+                range: start + pos..start + pos + def.dest.len(),
+                dest: def.dest.to_string(),
+            });
+        }
+    }
+
+    for (event, range) in parser {
+        let dest_url = match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            })
+            | Event::Start(Tag::Image {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            }) => dest_url,
+            _ => continue,
+        };
+        if dest_url.is_empty() {
+            continue;
+        }
+        let text = &src[range.clone()];
+        let Some(paren) = text.rfind("](") else {
+            continue;
+        };
+        let start = range.start + paren + 2;
+        if let Some(pos) = src[start..range.end].find(dest_url.as_ref()) {
+            ret.push(LinkDestination {
+                range: start + pos..start + pos + dest_url.len(),
+                dest: dest_url.to_string(),
+            });
+        }
+    }
+
+    ret.sort_by_key(|l| l.range.start);
+    ret
+}
+
+/// Finds the paths of `{{transclusions}}`, using the same syntax as `Wiki::read_file`.
+pub fn find_transclusions(src: &str) -> Vec<LinkDestination> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?m)^\{\{(.+?)\}\}$").unwrap();
+    }
+    RE.captures_iter(src)
+        .map(|caps| {
+            let m = caps.get(1).unwrap();
+            LinkDestination {
+                range: m.range(),
+                dest: m.as_str().to_owned(),
+            }
+        })
+        .collect()
+}
+
+/// Splits a link into the path and the query string or fragment, if any.
+pub fn split_link_suffix(dest: &str) -> (&str, &str) {
+    match dest.find(['?', '#']) {
+        Some(ndx) => dest.split_at(ndx),
+        None => (dest, ""),
+    }
+}
+
+/// The prefix of absolute links to pages in the wiki.
+const PAGE_PREFIX: &str = "/page/";
+
+/// Resolves a link on a page in `base_dir` to the path of a file in the wiki. Returns `None` for
+/// links that go somewhere other than a file in the wiki, such as external URLs.
+pub fn resolve_link(base_dir: &[&str], dest: &str) -> Option<Vec<String>> {
+    let (path, _) = split_link_suffix(dest);
+    if path.is_empty() || path.starts_with("//") {
+        return None;
+    }
+    if let Some((scheme, _)) = path.split_once(':') {
+        if !scheme.contains('/') {
+            return None;
+        }
+    }
+
+    let path = RawStr::new(path).percent_decode().ok()?;
+    let (mut ret, relative_path): (Vec<String>, &str) = match path.strip_prefix('/') {
+        Some(_) => (Vec::new(), path.strip_prefix(PAGE_PREFIX)?),
+        None => (base_dir.iter().map(|s| s.to_string()).collect(), &path),
+    };
+
+    for segment in relative_path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                ret.pop()?;
+            }
+            _ => ret.push(segment.to_owned()),
+        }
+    }
+
+    if ret.is_empty() {
+        None
+    } else {
+        Some(ret)
+    }
+}
+
+/// Creates a relative link from a page in `from_dir` to the file at `target`.
+pub fn relative_link<S: AsRef<str>>(from_dir: &[&str], target: &[S]) -> String {
+    let common = from_dir
+        .iter()
+        .zip(target)
+        .take_while(|(a, b)| **a == b.as_ref())
+        .count();
+    let mut ret = "../".repeat(from_dir.len() - common);
+    ret.push_str(&encode_path(&target[common..]));
+    ret
+}
+
+/// Joins path segments with `/`, percent-encoding everything in a segment except unreserved
+/// characters, so the result is safe as a Markdown link destination.
+fn encode_path<S: AsRef<str>>(segments: &[S]) -> String {
+    let encoded: Vec<String> = segments
+        .iter()
+        .map(|segment| {
+            let mut ret = String::new();
+            for byte in segment.as_ref().bytes() {
+                if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                    ret.push(byte as char);
+                } else {
+                    ret.push_str(&format!("%{:02X}", byte));
+                }
+            }
+            ret
+        })
+        .collect();
+    encoded.join("/")
+}
+
+/// Creates an absolute link to the file at `target`.
+pub fn absolute_link<S: AsRef<str>>(target: &[S]) -> String {
+    format!("{}{}", PAGE_PREFIX, encode_path(target))
+}
+
+/// Splits the source of a `[[wiki link]]` into the link text and the name of the page it points
+/// to. As in Gollum, the page comes last in `[[Link text|Page Name]]`.
+pub fn split_wiki_link(src: &str) -> Option<(&str, &str)> {
+    let inner = src.strip_prefix("[[")?.strip_suffix("]]")?;
+    Some(inner.split_once('|').unwrap_or((inner, inner)))
+}
+
+/// The source of the `[[wiki link]]` starting at `start`. The ranges the parser gives for wiki
+/// links stop short of the closing brackets.
+pub fn wiki_link_source(src: &str, start: usize) -> &str {
+    let source = &src[start..];
+    &source[..source.find("]]").map_or(source.len(), |ndx| ndx + 2)]
+}
+
+/// Finds the page names in `[[wiki links]]`.
+fn find_wiki_link_names(src: &str) -> Vec<&str> {
+    Parser::new_ext(src, markdown_options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                ..
+            }) => split_wiki_link(wiki_link_source(src, range.start)).map(|(_, name)| name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Finds the files a page in `base_dir` links to: the targets of its Markdown links and
+/// `[[wiki links]]`, and the files it transcludes. Wiki links to pages that do not exist yet
+/// count as links to the pages they would create.
+pub fn find_page_links(
+    base_dir: &[&str],
+    src: &str,
+    wiki_link_targets: &WikiLinkTargets,
+) -> Vec<Vec<String>> {
+    let markdown = find_link_destinations(src)
+        .into_iter()
+        .filter_map(|link| resolve_link(base_dir, &link.dest));
+    let wiki = find_wiki_link_names(src)
+        .into_iter()
+        .map(|name| wiki_link_targets.path(base_dir, name));
+    let transcluded = find_transclusions(src)
+        .into_iter()
+        .map(|link| link.dest.split('/').map(|s| s.to_owned()).collect());
+    let mut ret = Vec::new();
+    for target in markdown.chain(wiki).chain(transcluded) {
+        if !ret.contains(&target) {
+            ret.push(target);
+        }
+    }
+    ret
+}
+
+/// Page names in wiki links match file names regardless of case, and spaces match hyphens.
+fn normalize_page_name(name: &str) -> String {
+    name.trim().to_lowercase().replace(' ', "-")
+}
+
+/// Finds the files that `[[wiki links]]` point to.
+pub struct WikiLinkTargets {
+    /// Paths of the files in the wiki by their normalized file names and by their normalized
+    /// paths, each with and, for pages, without the extension.
+    by_name: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl WikiLinkTargets {
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(paths: I) -> Self {
+        let mut by_name: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+        for path in paths {
+            let segments: Vec<String> = path.split('/').map(|s| s.to_owned()).collect();
+            let mut keys = vec![path, &path[path.rfind('/').map_or(0, |ndx| ndx + 1)..]];
+            if let Some((path_stem, ext)) = path.rsplit_once('.') {
+                if is_page(ext) {
+                    keys.push(path_stem);
+                    keys.push(&path_stem[path_stem.rfind('/').map_or(0, |ndx| ndx + 1)..]);
+                }
+            }
+            for key in keys {
+                let targets = by_name.entry(normalize_page_name(key)).or_default();
+                if !targets.contains(&segments) {
+                    targets.push(segments.clone());
+                }
+            }
+        }
+        WikiLinkTargets { by_name }
+    }
+
+    /// Finds the file that `[[name]]` on a page in `base_dir` points to. Names with slashes are
+    /// paths, from `base_dir` or, failing that, from the top of the wiki. Otherwise the file can
+    /// be in any directory, and the one next to the page wins if there are several.
+    pub fn resolve(&self, base_dir: &[&str], name: &str) -> Option<&[String]> {
+        let name = name.trim();
+        if name.contains('/') && !name.starts_with('/') && !base_dir.is_empty() {
+            let relative = format!("{}/{}", base_dir.join("/"), name);
+            if let Some(targets) = self.by_name.get(&normalize_page_name(&relative)) {
+                return targets.first().map(|t| t.as_slice());
+            }
+        }
+        let targets = self
+            .by_name
+            .get(&normalize_page_name(name.trim_start_matches('/')))?;
+        targets
+            .iter()
+            .min_by_key(|target| {
+                let in_base_dir = target[..target.len() - 1] == *base_dir;
+                (!in_base_dir, target.len(), target.join("/"))
+            })
+            .map(|target| target.as_slice())
+    }
+
+    /// The path of the file `[[name]]` on a page in `base_dir` points to, or of the page that
+    /// would be created next to the page if there is no such file.
+    pub fn path(&self, base_dir: &[&str], name: &str) -> Vec<String> {
+        if let Some(target) = self.resolve(base_dir, name) {
+            return target.to_vec();
+        }
+        let name = name.trim();
+        let mut path: Vec<String> = match name.strip_prefix('/') {
+            Some(_) => Vec::new(),
+            None => base_dir.iter().map(|s| s.to_string()).collect(),
+        };
+        path.extend(
+            name.split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.replace(' ', "-")),
+        );
+        if let Some(file_name) = path.last_mut() {
+            if !file_name
+                .rsplit_once('.')
+                .is_some_and(|(_, ext)| is_page(ext))
+            {
+                file_name.push_str(".md");
+            }
+        }
+        path
+    }
+
+    /// The URL of the file `[[name]]` on a page in `base_dir` points to, or of the editor for
+    /// creating it next to the page if there is no such file.
+    pub fn url(&self, base_dir: &[&str], name: &str) -> String {
+        match self.resolve(base_dir, name) {
+            Some(target) => absolute_link(target),
+            None => new_page_link(&self.path(base_dir, name)),
+        }
+    }
+}
+
+/// Where pages that do not exist yet are created.
+const NEW_PAGE_PREFIX: &str = "/new/";
+
+/// Creates a link to the editor for creating the page at `target`.
+pub fn new_page_link<S: AsRef<str>>(target: &[S]) -> String {
+    format!("{}{}", NEW_PAGE_PREFIX, encode_path(target))
+}
+
+/// Applies replacements to non-overlapping ranges of `src`.
+fn replace_ranges(src: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut ret = String::with_capacity(src.len());
+    let mut last = 0;
+    for (range, replacement) in edits {
+        ret.push_str(&src[last..range.start]);
+        ret.push_str(&replacement);
+        last = range.end;
+    }
+    ret.push_str(&src[last..]);
+    ret
+}
+
+/// Rewrites the links in a page after the file at `old_path` moved to `new_path`.
+///
+/// The page itself was in `dir_before` and is now in `dir_after`; these are the same unless the
+/// page being rewritten is the one that moved. Relative links are kept relative and are updated
+/// to point at the same files from the page's new location. Returns `None` if nothing changed.
+pub fn rewrite_for_rename(
+    src: &str,
+    dir_before: &[&str],
+    dir_after: &[&str],
+    old_path: &[&str],
+    new_path: &[&str],
+) -> Option<String> {
+    let mut edits = Vec::new();
+
+    for link in find_link_destinations(src) {
+        let Some(target) = resolve_link(dir_before, &link.dest) else {
+            continue;
+        };
+        let moved = target == old_path;
+        if !moved && dir_before == dir_after {
+            continue;
+        }
+        let new_target: Vec<&str> = if moved {
+            new_path.to_vec()
+        } else {
+            target.iter().map(|s| s.as_str()).collect()
+        };
+        let (_, suffix) = split_link_suffix(&link.dest);
+        let new_dest = if link.dest.starts_with('/') {
+            if !moved {
+                continue;
+            }
+            absolute_link(&new_target)
+        } else {
+            relative_link(dir_after, &new_target)
+        } + suffix;
+        if new_dest != link.dest {
+            edits.push((link.range, new_dest));
+        }
+    }
+
+    let old_transclusion = old_path.join("/");
+    for transclusion in find_transclusions(src) {
+        if transclusion.dest == old_transclusion {
+            edits.push((transclusion.range, new_path.join("/")));
+        }
+    }
+
+    if edits.is_empty() {
+        None
+    } else {
+        Some(replace_ranges(src, edits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_link_destinations() {
+        let src = "[a](a.md) ![img](pics/b.png \"title\") <https://example.com> [c][ref]\n\n[ref]: c.md\n";
+        let links = find_link_destinations(src);
+        let dests: Vec<&str> = links.iter().map(|l| l.dest.as_str()).collect();
+        assert_eq!(vec!["a.md", "pics/b.png", "c.md"], dests);
+        for link in links {
+            assert_eq!(link.dest, &src[link.range]);
+        }
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        assert_eq!(
+            Some(vec!["dir".to_owned(), "page.md".to_owned()]),
+            resolve_link(&["dir"], "page.md#heading")
+        );
+        assert_eq!(
+            Some(vec!["other".to_owned(), "My Page.md".to_owned()]),
+            resolve_link(&["dir"], "../other/My%20Page.md")
+        );
+        assert_eq!(
+            Some(vec!["top.md".to_owned()]),
+            resolve_link(&["dir"], "/page/top.md")
+        );
+        assert_eq!(None, resolve_link(&["dir"], "https://example.com/page.md"));
+        assert_eq!(None, resolve_link(&["dir"], "mailto:someone@example.com"));
+        assert_eq!(None, resolve_link(&["dir"], "#heading"));
+        assert_eq!(None, resolve_link(&["dir"], "/search?q=stuff"));
+        assert_eq!(None, resolve_link(&[], "../escape.md"));
+    }
+
+    #[test]
+    fn test_relative_link() {
+        assert_eq!("b.md", relative_link(&["a"], &["a", "b.md"]));
+        assert_eq!("../c/b.md", relative_link(&["a"], &["c", "b.md"]));
+        assert_eq!("a/My%20Page.md", relative_link(&[], &["a", "My Page.md"]));
+    }
+
+    #[test]
+    fn test_split_wiki_link() {
+        assert_eq!(Some(("Page", "Page")), split_wiki_link("[[Page]]"));
+        assert_eq!(
+            Some(("Link text", "Page Name")),
+            split_wiki_link("[[Link text|Page Name]]")
+        );
+        assert_eq!(None, split_wiki_link("[Page]"));
+    }
+
+    #[test]
+    fn test_wiki_link_targets() {
+        let targets = WikiLinkTargets::new([
+            "Home.md",
+            "docs/Getting-Started.md",
+            "notes/Setup.md",
+            "docs/setup.md",
+            "pics/diagram.png",
+        ]);
+        assert_eq!("/page/Home.md", targets.url(&["docs"], "home"));
+        assert_eq!(
+            "/page/docs/Getting-Started.md",
+            targets.url(&[], "Getting Started")
+        );
+        assert_eq!("/page/docs/setup.md", targets.url(&["docs"], "Setup"));
+        assert_eq!("/page/notes/Setup.md", targets.url(&["notes"], "Setup"));
+        assert_eq!("/page/notes/Setup.md", targets.url(&[], "notes/setup"));
+        assert_eq!("/page/pics/diagram.png", targets.url(&[], "diagram.png"));
+        assert_eq!("/new/docs/New-Page.md", targets.url(&["docs"], "New Page"));
+        assert_eq!("/new/New-Page.md", targets.url(&["docs"], "/New Page"));
+    }
+
+    #[test]
+    fn test_find_page_links() {
+        let targets = WikiLinkTargets::new(["Home.md", "docs/Setup.md"]);
+        let src = "[Home](../Home.md#top) [[setup]] [[Later Page]] [[Home]]\n{{docs/inc.md}}\n[web](https://example.com)\n";
+        let links = find_page_links(&["docs"], src, &targets);
+        assert_eq!(
+            vec![
+                vec!["Home.md"],
+                vec!["docs", "Setup.md"],
+                vec!["docs", "Later-Page.md"],
+                vec!["docs", "inc.md"],
+            ],
+            links
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_to_moved_page() {
+        let src = "See [old](old.md#top) and [abs](/page/old.md).\n{{old.md}}\n[other](other.md)\n";
+        let rewritten = rewrite_for_rename(src, &[], &[], &["old.md"], &["dir", "new.md"]);
+        assert_eq!(
+            Some("See [old](dir/new.md#top) and [abs](/page/dir/new.md).\n{{dir/new.md}}\n[other](other.md)\n".to_owned()),
+            rewritten
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_in_moved_page() {
+        let src = "[sibling](sibling.md) [self](old.md) [external](https://example.com)";
+        let rewritten = rewrite_for_rename(src, &["a"], &["b"], &["a", "old.md"], &["b", "new.md"]);
+        assert_eq!(
+            Some(
+                "[sibling](../a/sibling.md) [self](new.md) [external](https://example.com)"
+                    .to_owned()
+            ),
+            rewritten
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_unchanged() {
+        let src = "[other](./other.md)";
+        assert_eq!(
+            None,
+            rewrite_for_rename(src, &[], &[], &["old.md"], &["new.md"])
+        );
+    }
+}
@@ -5,7 +5,10 @@ extern crate rocket;
 extern crate lazy_static;
 
 mod assets;
+mod diff;
 mod error;
+mod links;
+mod merge;
 mod page;
 mod repository;
 mod requests;
@@ -21,19 +24,30 @@ use wiki::Wiki;
 
 fn create_wiki() -> Result<Wiki, MyError> {
     let args = settings::Args::parse();
-    let git_repo = args
-        .git_repo()
-        .unwrap_or_else(|| std::env::current_dir().unwrap());
+    let git_repo = args.git_repo();
 
     println!("Loading wiki in {}", git_repo.display());
 
     let repo = create_repository(args.use_fs(), git_repo)?;
+    // Switch before reading the settings, so that smeagol.toml comes from the requested branch.
+    let from_args = args.reference().is_some();
+    if let Some(branch) = args.reference() {
+        repo.set_branch(branch)?;
+    }
     let settings = parse_settings_from_args(args, &repo)?;
+    // Otherwise the branch may come from smeagol.toml, which could only be read now.
+    if let Some(branch) = settings.branch().filter(|_| !from_args) {
+        repo.set_branch(branch)?;
+    }
     Wiki::new(settings, repo)
 }
 
+/// The largest file that can be uploaded from the editor. Rocket's default of 1 MiB is too
+/// small for screenshots and PDFs.
+const UPLOAD_LIMIT: &str = "25 MiB";
+
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> Result<(), Box<rocket::Error>> {
     let wiki = match create_wiki() {
         Ok(wiki) => wiki,
         Err(err) => {
@@ -42,13 +56,18 @@ async fn main() -> Result<(), rocket::Error> {
         }
     };
     println!("Wiki loaded");
+    wiki.start_indexing();
+    wiki.start_sync_timer();
+    wiki.start_watcher();
 
     let address = wiki.settings().host();
     let port = wiki.settings().port();
 
     let figment = rocket::Config::figment()
         .merge(("port", port))
-        .merge(("address", address));
+        .merge(("address", address))
+        .merge(("limits.file", UPLOAD_LIMIT))
+        .merge(("limits.data-form", UPLOAD_LIMIT));
     let rocket = rocket::custom(figment);
     let rocket = rocket.manage(wiki);
     let rocket = requests::mount_routes(rocket);
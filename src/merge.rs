@@ -0,0 +1,145 @@
+use similar::capture_diff_slices;
+use similar::Algorithm;
+use similar::DiffOp;
+
+/// The outcome of a three-way merge.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MergeResult {
+    pub content: String,
+    /// The number of regions changed on both sides. Each one is written out between conflict
+    /// markers.
+    pub conflicts: usize,
+}
+
+/// For every line of `base`, the line of `other` it was left unchanged as, if any.
+fn unchanged_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut ret = vec![None; base.len()];
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        if let DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for ndx in 0..len {
+                ret[old_index + ndx] = Some(new_index + ndx);
+            }
+        }
+    }
+    ret
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+}
+
+fn push_conflict_side(out: &mut String, lines: &[&str]) {
+    push_lines(out, lines);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Merges the changes made in `ours` and in `theirs`, which were both based on `base`.
+///
+/// Regions changed on only one side take that side's version. Regions changed differently on
+/// both sides are kept as conflicts, with our version first.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let in_ours = unchanged_lines(&base, &ours);
+    let in_theirs = unchanged_lines(&base, &theirs);
+
+    let mut content = String::new();
+    let mut conflicts = 0;
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    while o < base.len() || a < ours.len() || b < theirs.len() {
+        // Lines unchanged on both sides.
+        let mut len = 0;
+        while o + len < base.len()
+            && in_ours[o + len] == Some(a + len)
+            && in_theirs[o + len] == Some(b + len)
+        {
+            len += 1;
+        }
+        if len > 0 {
+            push_lines(&mut content, &base[o..o + len]);
+            o += len;
+            a += len;
+            b += len;
+            continue;
+        }
+
+        // Something changed; the region lasts until the next line unchanged on both sides.
+        let (o_end, a_end, b_end) = (o..base.len())
+            .find_map(|ndx| Some((ndx, in_ours[ndx]?, in_theirs[ndx]?)))
+            .unwrap_or((base.len(), ours.len(), theirs.len()));
+        let base_region = &base[o..o_end];
+        let our_region = &ours[a..a_end];
+        let their_region = &theirs[b..b_end];
+        if our_region == base_region || our_region == their_region {
+            push_lines(&mut content, their_region);
+        } else if their_region == base_region {
+            push_lines(&mut content, our_region);
+        } else {
+            conflicts += 1;
+            content.push_str("<<<<<<< your changes\n");
+            push_conflict_side(&mut content, our_region);
+            content.push_str("=======\n");
+            push_conflict_side(&mut content, their_region);
+            content.push_str(">>>>>>> current version\n");
+        }
+        (o, a, b) = (o_end, a_end, b_end);
+    }
+
+    MergeResult { content, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_non_overlapping() {
+        let base = "one\ntwo\nthree\nfour\n";
+        let ours = "ONE\ntwo\nthree\nfour\n";
+        let theirs = "one\ntwo\nthree\nFOUR\nfive\n";
+        assert_eq!(
+            MergeResult {
+                content: "ONE\ntwo\nthree\nFOUR\nfive\n".to_owned(),
+                conflicts: 0
+            },
+            merge3(base, ours, theirs)
+        );
+    }
+
+    #[test]
+    fn test_merge_same_change() {
+        let base = "one\ntwo\n";
+        let changed = "one\n2\n";
+        assert_eq!(
+            MergeResult {
+                content: changed.to_owned(),
+                conflicts: 0
+            },
+            merge3(base, changed, changed)
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let base = "one\ntwo\nthree";
+        let ours = "one\nmine\nthree";
+        let theirs = "one\ntheirs\nthree";
+        assert_eq!(
+            MergeResult {
+                content: "one\n<<<<<<< your changes\nmine\n=======\ntheirs\n>>>>>>> current version\nthree".to_owned(),
+                conflicts: 1
+            },
+            merge3(base, ours, theirs)
+        );
+    }
+}
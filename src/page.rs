@@ -1,15 +1,93 @@
-use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{
+    html, Event, HeadingLevel, LinkType, MetadataBlockKind, Options, Parser, Tag, TagEnd,
+};
+use serde::Deserialize;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::error::MyError;
+use crate::links::split_wiki_link;
+use crate::links::wiki_link_source;
 use crate::settings::Settings;
 
 struct MarkdownPage<'a> {
     title: String,
+    front_matter: FrontMatter,
+    /// The source after the front matter.
+    body: &'a str,
     events: Vec<Event<'a>>,
 }
 
+/// Metadata from the YAML (between `---` lines) or TOML (between `+++` lines) block at the top
+/// of a page. Other fields, such as those used by static site generators, are ignored.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+    pub draft: bool,
+}
+
+/// Accepts a single string where a list is expected, as in `tags: notes`.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Splits the front matter off the top of a Markdown page. Returns the error instead if the front
+/// matter cannot be parsed, in which case it is left in place so that it is shown instead of
+/// silently dropped.
+fn split_front_matter(src: &str) -> Result<(FrontMatter, &str), String> {
+    let options = markdown_options()
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    let mut parser = Parser::new_ext(src, options).into_offset_iter();
+    let Some((Event::Start(Tag::MetadataBlock(kind)), range)) = parser.next() else {
+        return Ok((FrontMatter::default(), src));
+    };
+    let mut text = String::new();
+    for (event, _) in parser.by_ref() {
+        match event {
+            Event::Text(value) => text.push_str(&value),
+            _ => break,
+        }
+    }
+    let front_matter = match kind {
+        MetadataBlockKind::YamlStyle => serde_yaml::from_str(&text).map_err(|e| e.to_string())?,
+        MetadataBlockKind::PlusesStyle => toml::from_str(&text).map_err(|e| e.to_string())?,
+    };
+    let body = &src[range.end..];
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))
+        .unwrap_or(body);
+    Ok((front_matter, body))
+}
+
+/// Shows why the front matter of a page could not be parsed, above the rest of the page.
+fn front_matter_error(error: &str) -> [Event<'static>; 3] {
+    [
+        Event::Html("<pre class=\"flash flash-error\">".into()),
+        Event::Text(format!("Failed to parse front matter: {}", error).into()),
+        Event::Html("</pre>\n".into()),
+    ]
+}
+
 fn try_get_h1_title(
     settings: &Settings,
     fallback_file_name: &str,
@@ -41,18 +119,155 @@ fn try_get_h1_title(
     fallback_file_name.to_owned()
 }
 
+/// The Markdown extensions enabled when parsing pages.
+pub fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    options.insert(Options::ENABLE_WIKILINKS);
+    options
+}
+
+/// A link on a page whose target is looked up in the wiki.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PageLink {
+    /// The page name in a `[[wiki link]]`.
+    Wiki(String),
+    /// The destination of an ordinary Markdown link.
+    Markdown(String),
+}
+
+/// Where a link on a page goes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkTarget {
+    pub url: String,
+    /// Links to pages that do not exist go to the editor for creating them, and are marked.
+    pub exists: bool,
+}
+
+/// Looks up where a link on a page goes. Returning `None` for a Markdown link leaves it as
+/// written.
+pub type LinkResolver<'a> = &'a dyn Fn(&PageLink) -> Option<LinkTarget>;
+
+/// The class of links to pages that do not exist yet.
+const MISSING_PAGE_CLASS: &str = "missing-page";
+
+/// The opening tag of a link to a page that does not exist yet. The HTML renderer has no way of
+/// adding a class to a link, so the tag it renders is patched instead.
+fn missing_link_start(url: &str, title: &str) -> Event<'static> {
+    let link = Tag::Link {
+        link_type: LinkType::Inline,
+        dest_url: url.into(),
+        title: title.into(),
+        id: "".into(),
+    };
+    let mut tag = String::new();
+    html::push_html(
+        &mut tag,
+        [Event::Start(link), Event::End(TagEnd::Link)].into_iter(),
+    );
+    let tag = tag.trim_end_matches("</a>").replacen(
+        "<a ",
+        &format!("<a class=\"{}\" ", MISSING_PAGE_CLASS),
+        1,
+    );
+    Event::InlineHtml(tag.into())
+}
+
+/// Parses a page, turning `[[wiki links]]` into ordinary links and marking links to pages that do
+/// not exist.
+fn parse_markdown<'a>(src: &'a str, resolve_link: LinkResolver) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    // Links to missing pages start with HTML, so they have to end with HTML too.
+    let mut in_missing_link = false;
+    let mut parser = Parser::new_ext(src, markdown_options()).into_offset_iter();
+    while let Some((event, range)) = parser.next() {
+        match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                ..
+            }) => {
+                // The parser takes the part before the `|` to be the page, which is the other way
+                // around from Gollum, so use the source instead of what it parsed.
+                for (event, _) in parser.by_ref() {
+                    if event == Event::End(TagEnd::Link) {
+                        break;
+                    }
+                }
+                let (text, name) =
+                    split_wiki_link(wiki_link_source(src, range.start)).unwrap_or_default();
+                let target = resolve_link(&PageLink::Wiki(name.to_owned())).unwrap_or_default();
+                if target.exists {
+                    events.push(Event::Start(Tag::Link {
+                        link_type: LinkType::Inline,
+                        dest_url: target.url.into(),
+                        title: "".into(),
+                        id: "".into(),
+                    }));
+                    events.push(Event::Text(text.trim().into()));
+                    events.push(Event::End(TagEnd::Link));
+                } else {
+                    events.push(missing_link_start(&target.url, ""));
+                    events.push(Event::Text(text.trim().into()));
+                    events.push(Event::InlineHtml("</a>".into()));
+                }
+            }
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => match resolve_link(&PageLink::Markdown(dest_url.to_string())) {
+                Some(target) if !target.exists => {
+                    events.push(missing_link_start(&target.url, &title));
+                    in_missing_link = true;
+                }
+                _ => events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                })),
+            },
+            Event::End(TagEnd::Link) if in_missing_link => {
+                events.push(Event::InlineHtml("</a>".into()));
+                in_missing_link = false;
+            }
+            event => events.push(event),
+        }
+    }
+    events
+}
+
 impl<'a> MarkdownPage<'a> {
-    fn new(settings: &'a Settings, file_name: &'a str, src: &'a str) -> MarkdownPage<'a> {
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_TABLES);
-        options.insert(Options::ENABLE_FOOTNOTES);
-        options.insert(Options::ENABLE_STRIKETHROUGH);
-        options.insert(Options::ENABLE_TASKLISTS);
-        options.insert(Options::ENABLE_SMART_PUNCTUATION);
-        let mut events: Vec<Event<'a>> = Parser::new_ext(src, options).collect();
-        let title = try_get_h1_title(settings, file_name, &mut events);
+    fn new(
+        settings: &'a Settings,
+        file_name: &'a str,
+        src: &'a str,
+        resolve_link: LinkResolver,
+    ) -> MarkdownPage<'a> {
+        let (front_matter, body, error) = match split_front_matter(src) {
+            Ok((front_matter, body)) => (front_matter, body, None),
+            Err(error) => (FrontMatter::default(), src, Some(error)),
+        };
+        let mut events = parse_markdown(body, resolve_link);
+        let title = match &front_matter.title {
+            Some(title) => title.clone(),
+            None => try_get_h1_title(settings, file_name, &mut events),
+        };
+        if let Some(error) = error {
+            events.splice(0..0, front_matter_error(&error));
+        }
 
-        MarkdownPage { title, events }
+        MarkdownPage {
+            title,
+            front_matter,
+            body,
+            events,
+        }
     }
 
     fn title(&'a self) -> &'a str {
@@ -66,9 +281,11 @@ impl<'a> MarkdownPage<'a> {
     }
 }
 
+#[derive(Clone)]
 pub struct Page {
     pub title: String,
     pub body: String,
+    pub front_matter: FrontMatter,
 }
 
 // TODO: figure out how to group methods together by language. Maybe using trait objects?
@@ -92,30 +309,37 @@ impl MarkupLanguage {
         file_stem: &str,
         file_contents: &str,
         settings: &Settings,
+        resolve_link: LinkResolver,
     ) -> Result<Page, MyError> {
         match self {
             MarkupLanguage::Markdown => {
-                let markdown_page = MarkdownPage::new(settings, file_stem, file_contents);
+                let markdown_page =
+                    MarkdownPage::new(settings, file_stem, file_contents, resolve_link);
 
                 let title = markdown_page.title().to_owned();
+                let front_matter = markdown_page.front_matter.clone();
                 let body = markdown_page.render_html();
 
-                Ok(Page { title, body })
+                Ok(Page {
+                    title,
+                    body,
+                    front_matter,
+                })
             }
         }
     }
 
-    fn raw<S: Into<String>>(&self, file_stem: &str, file_contents: S, settings: &Settings) -> Page {
-        let file_contents: String = file_contents.into();
+    fn raw(&self, file_stem: &str, file_contents: &str, settings: &Settings) -> Page {
         match self {
             MarkupLanguage::Markdown => {
-                let markdown_page = MarkdownPage::new(settings, file_stem, &file_contents);
-
-                let title = markdown_page.title().to_owned();
+                // Only the title is needed, and links cannot be part of it.
+                let markdown_page =
+                    MarkdownPage::new(settings, file_stem, file_contents, &|_| None);
 
                 Page {
-                    title,
-                    body: file_contents,
+                    title: markdown_page.title,
+                    body: markdown_page.body.to_owned(),
+                    front_matter: markdown_page.front_matter,
                 }
             }
         }
@@ -139,6 +363,7 @@ pub fn get_page(
     file_extension: &str,
     bytes: &[u8],
     settings: &Settings,
+    resolve_link: LinkResolver,
 ) -> Result<Option<Page>, MyError> {
     match get_language_for_file_extension(file_extension) {
         None => Ok(None),
@@ -146,6 +371,7 @@ pub fn get_page(
             file_stem,
             std::str::from_utf8(bytes)?,
             settings,
+            resolve_link,
         )?)),
     }
 }
@@ -179,7 +405,7 @@ mod tests {
     fn test_normal_title() {
         let settings = Settings::new("Home", false);
         let input = "# First H1\n# Second H1";
-        let markdown_page = MarkdownPage::new(&settings, "file_name", &input);
+        let markdown_page = MarkdownPage::new(&settings, "file_name", input, &|_| None);
         assert_eq!("file_name", markdown_page.title());
         let rendered = markdown_page.render_html();
         assert_eq!("<h1>First H1</h1>\n<h1>Second H1</h1>\n", rendered);
@@ -189,7 +415,7 @@ mod tests {
     fn test_h1_title() {
         let settings = Settings::new("Home", true);
         let input = "# First H1\n# Second H1";
-        let markdown_page = MarkdownPage::new(&settings, "file_name", &input);
+        let markdown_page = MarkdownPage::new(&settings, "file_name", input, &|_| None);
         assert_eq!("First H1", markdown_page.title());
         let rendered = markdown_page.render_html();
         assert_eq!("<h1>Second H1</h1>\n", rendered);
@@ -199,9 +425,118 @@ mod tests {
     fn test_h1_title_complicated() {
         let settings = Settings::new("Home", true);
         let input = "# Austin\'s Wiki\nwords words words";
-        let markdown_page = MarkdownPage::new(&settings, "file_name", &input);
+        let markdown_page = MarkdownPage::new(&settings, "file_name", input, &|_| None);
         assert_eq!("Austin\u{2019}s Wiki", markdown_page.title());
         let rendered = markdown_page.render_html();
         assert_eq!("<p>words words words</p>\n", rendered);
     }
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let settings = Settings::new("Home", true);
+        let input = "---\ntitle: From Front Matter\ntags: notes\naliases: [Old Name]\nlayout: post\n---\n# Heading\ntext\n";
+        let page = get_page("file_name", "md", input.as_bytes(), &settings, &|_| None)
+            .unwrap()
+            .unwrap();
+        assert_eq!("From Front Matter", page.title);
+        assert_eq!("<h1>Heading</h1>\n<p>text</p>\n", page.body);
+        assert_eq!(
+            FrontMatter {
+                title: Some("From Front Matter".to_owned()),
+                tags: vec!["notes".to_owned()],
+                aliases: vec!["Old Name".to_owned()],
+                description: None,
+                draft: false,
+            },
+            page.front_matter
+        );
+    }
+
+    #[test]
+    fn test_toml_front_matter() {
+        let settings = Settings::new("Home", false);
+        let input = "+++\ndescription = \"About things\"\ndraft = true\n+++\ntext\n";
+        let page = get_raw_page("file_name", "md", input.as_bytes(), &settings)
+            .unwrap()
+            .unwrap();
+        assert_eq!("file_name", page.title);
+        assert_eq!("text\n", page.body);
+        assert_eq!(
+            Some("About things"),
+            page.front_matter.description.as_deref()
+        );
+        assert!(page.front_matter.draft);
+    }
+
+    #[test]
+    fn test_invalid_front_matter_is_kept() {
+        let settings = Settings::new("Home", true);
+        let input = "+++\ntags = [unclosed\n+++\n# Title\n";
+        assert!(split_front_matter(input).is_err());
+        let page = get_page("file_name", "md", input.as_bytes(), &settings, &|_| None)
+            .unwrap()
+            .unwrap();
+        assert_eq!("file_name", page.title);
+        assert_eq!(FrontMatter::default(), page.front_matter);
+        assert!(page
+            .body
+            .starts_with("<pre class=\"flash flash-error\">Failed to parse front matter: "));
+        assert!(page.body.contains("<p>+++\ntags = [unclosed\n+++</p>"));
+    }
+
+    #[test]
+    fn test_invalid_yaml_front_matter_is_kept() {
+        let settings = Settings::new("Home", false);
+        let input = "---\ntags: [unclosed\n---\ntext\n";
+        assert!(split_front_matter(input).is_err());
+        let page = get_page("file_name", "md", input.as_bytes(), &settings, &|_| None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(FrontMatter::default(), page.front_matter);
+        assert!(page
+            .body
+            .starts_with("<pre class=\"flash flash-error\">Failed to parse front matter: "));
+        assert!(page.body.contains("tags: [unclosed"));
+    }
+
+    #[test]
+    fn test_wiki_links() {
+        let settings = Settings::new("Home", false);
+        let input = "See [[Other Page]] and [[the docs|Docs Page]].";
+        let resolve = |link: &PageLink| match link {
+            PageLink::Wiki(name) => Some(LinkTarget {
+                url: format!("/page/{}.md", name.replace(' ', "-")),
+                exists: name == "Other Page",
+            }),
+            PageLink::Markdown(_) => None,
+        };
+        let page = get_page("file_name", "md", input.as_bytes(), &settings, &resolve)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            "<p>See <a href=\"/page/Other-Page.md\">Other Page</a> and <a class=\"missing-page\" href=\"/page/Docs-Page.md\">the docs</a>.</p>\n",
+            page.body
+        );
+    }
+
+    #[test]
+    fn test_missing_page_links() {
+        let settings = Settings::new("Home", false);
+        let input =
+            "[Here](Here.md), [there](There.md \"A <title>\") and [away](https://example.com).";
+        let resolve = |link: &PageLink| match link {
+            PageLink::Markdown(dest) if dest == "There.md" => Some(LinkTarget {
+                url: "/new/There.md".to_owned(),
+                exists: false,
+            }),
+            _ => None,
+        };
+        let page = get_page("file_name", "md", input.as_bytes(), &settings, &resolve)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            "<p><a href=\"Here.md\">Here</a>, <a class=\"missing-page\" href=\"/new/There.md\" title=\"A &lt;title&gt;\">there</a> and <a href=\"https://example.com\">away</a>.</p>\n",
+            page.body
+        );
+    }
 }
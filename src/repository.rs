@@ -1,18 +1,26 @@
 use std::{
+    collections::HashMap,
     io::{Read, Write},
     ops::{Deref, DerefMut},
-    path::PathBuf,
-    sync::Mutex,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    time::Duration,
 };
 
 use bitflags::bitflags;
 use git2::ObjectType;
+use git2::Oid;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use time::OffsetDateTime;
+use time::UtcOffset;
 
 use crate::error::MyError;
 
 bitflags! {
     pub struct RepositoryCapability: u32 {
         const SUPPORTS_EDIT_MESSAGE = 0b00000001;
+        const SUPPORTS_HISTORY = 0b00000010;
+        const SUPPORTS_BRANCHES = 0b00000100;
     }
 }
 
@@ -23,15 +31,244 @@ pub enum RepositoryItem {
     File(String),
 }
 
+/// A commit that changed a file, as shown in the history of a page.
+#[derive(Clone)]
+pub struct CommitInfo {
+    pub id: String,
+    pub short_id: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub time: OffsetDateTime,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLineKind {
+    Context,
+    Addition,
+    Deletion,
+}
+
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    /// The text of the line, without the trailing newline.
+    pub content: String,
+}
+
+pub struct DiffHunk {
+    pub header: String,
+    pub lines: Vec<DiffLine>,
+}
+
+/// A run of consecutive lines that were last changed by the same commit.
+pub struct BlameHunk {
+    pub commit: CommitInfo,
+    /// Line numbers (starting at 1) and the text of each line.
+    pub lines: Vec<(usize, String)>,
+}
+
+/// Who to record as the author of a commit.
+#[derive(Debug, Clone)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+}
+
+impl Author {
+    /// Combines a name and an email address that were given separately, such as in a form.
+    /// Blank values count as missing, and giving only one of the two is an error.
+    pub fn from_parts(name: Option<&str>, email: Option<&str>) -> Result<Option<Self>, MyError> {
+        let name = name.map(str::trim).filter(|name| !name.is_empty());
+        let email = email.map(str::trim).filter(|email| !email.is_empty());
+        match (name, email) {
+            (None, None) => Ok(None),
+            (Some(name), Some(email)) => Ok(Some(Author {
+                name: name.to_owned(),
+                email: email.to_owned(),
+            })),
+            _ => Err(MyError::InvalidAuthor),
+        }
+    }
+}
+
+/// What happened to the served branch when pulling from a remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PullResult {
+    UpToDate,
+    FastForwarded,
+    Merged,
+    /// Both sides changed these files in ways that could not be merged, so nothing was merged.
+    Conflicted(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncResult {
+    pub pull: PullResult,
+    /// The files that changed in the served branch, with `/` separating directories.
+    pub changed_files: Vec<String>,
+    pub pushed: bool,
+}
+
+/// A file to write along with another change, such as a rename.
+pub struct UpdatedFile {
+    pub path: Vec<String>,
+    pub content: String,
+}
+
+impl UpdatedFile {
+    pub fn path(&self) -> Vec<&str> {
+        self.path.iter().map(|s| s.as_str()).collect()
+    }
+}
+
 pub trait Repository {
     fn capabilities(&self) -> RepositoryCapability;
+    /// Lists the commits that changed the given file, newest first.
+    fn history(&self, file_path: &[&str]) -> Result<Vec<CommitInfo>, MyError>;
+    fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError>;
+    /// Reads a file as it was in the given revision, which can be anything `git rev-parse`
+    /// understands, such as a commit ID, tag, or branch.
+    fn read_file_at_revision(&self, file_path: &[&str], revision: &str)
+        -> Result<Vec<u8>, MyError>;
+    fn get_commit(&self, revision: &str) -> Result<CommitInfo, MyError>;
+    /// The latest commit on the branch being served.
+    fn head_commit(&self) -> Result<CommitInfo, MyError>;
+    /// Lists the names of the local branches.
+    fn branches(&self) -> Result<Vec<String>, MyError>;
+    /// The name of the branch being served, if it is a branch.
+    fn current_branch(&self) -> Option<String>;
+    /// Serves and commits to a different local branch from now on.
+    fn set_branch(&self, name: &str) -> Result<(), MyError>;
+    /// Fetches the served branch from a remote, merges it, and pushes the result back.
+    fn sync(&self, remote: &str, author: Option<&Author>) -> Result<SyncResult, MyError>;
+    /// Lists the paths of the files that differ between two revisions.
+    fn changed_files(&self, from: &str, to: &str) -> Result<Vec<String>, MyError>;
+    /// Finds the commit that last changed each line of a file.
+    fn blame(&self, file_path: &[&str]) -> Result<Vec<BlameHunk>, MyError>;
+    /// Line diff of a file between two revisions. A file that does not exist in a revision is
+    /// treated as empty.
+    fn diff_file(&self, file_path: &[&str], from: &str, to: &str)
+        -> Result<Vec<DiffHunk>, MyError>;
+    /// Writes a file. Commits are made by `author`, or by the user configured in Git if `None`.
+    fn write_file(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        author: Option<&Author>,
+    ) -> Result<(), MyError>;
+    /// Like `write_file`, but the commit is only made if the branch is still at the commit
+    /// `parent`, and fails with `MyError::HeadMoved` otherwise. Used to write content that was
+    /// based on the file as of `parent`.
+    fn write_file_on(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        parent: &str,
+        author: Option<&Author>,
+    ) -> Result<(), MyError>;
+    /// Moves a file. The `updated_files` are written as part of the same change, so that links
+    /// to the moved file can be fixed in the same commit. If `parent` is given, fails with
+    /// `MyError::HeadMoved` unless the branch is still at that commit.
+    fn rename_file(
+        &self,
+        old_path: &[&str],
+        new_path: &[&str],
+        message: &str,
+        updated_files: &[UpdatedFile],
+        parent: Option<&str>,
+        author: Option<&Author>,
+    ) -> Result<(), MyError>;
+    fn delete_file(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        author: Option<&Author>,
+    ) -> Result<(), MyError>;
+    fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError>;
+    fn file_exists(&self, path: &[&str]) -> Result<bool, MyError>;
+    fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError>;
+    /// Lists every file being served, in one pass.
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError>;
+    /// Creates a reader for the files returned by `list_files`.
+    fn file_reader(&self) -> Result<Box<dyn FileReader>, MyError>;
+    /// Starts watching for changes made outside of the wiki, such as by `git pull` or a text
+    /// editor, and reports them to `on_change` for as long as the wiki runs.
+    fn watch(&self, on_change: ChangeCallback) -> Result<(), MyError>;
+    /// Pins the files being served, so that a series of reads all see the same revision.
+    fn snapshot(&self) -> Result<Box<dyn RepositorySnapshot + '_>, MyError>;
+    /// Pins the files as they were in the given revision, which can be anything `git rev-parse`
+    /// understands.
+    fn snapshot_at(&self, revision: &str) -> Result<Box<dyn RepositorySnapshot + '_>, MyError>;
+}
+
+/// The files being served as of one moment, as returned by `Repository::snapshot`.
+pub trait RepositorySnapshot {
+    /// The commit the snapshot is pinned to, for repositories with history.
+    fn commit_id(&self) -> Option<&str>;
+    /// An ID for the contents of a file, which changes whenever the contents do. `None` if the
+    /// file does not exist or the repository does not have such IDs.
+    fn file_id(&self, file_path: &[&str]) -> Option<String>;
     fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError>;
-    fn write_file(&self, file_path: &[&str], message: &str, content: &str) -> Result<(), MyError>;
     fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError>;
     fn file_exists(&self, path: &[&str]) -> Result<bool, MyError>;
     fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError>;
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError>;
 }
 
+/// A snapshot of a repository without history, which reads the files as they are at the time
+/// of each read. This is the best that can be done without copying them.
+pub struct LiveSnapshot<'a>(pub &'a (dyn Repository + 'a));
+
+impl RepositorySnapshot for LiveSnapshot<'_> {
+    fn commit_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn file_id(&self, _file_path: &[&str]) -> Option<String> {
+        None
+    }
+
+    fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
+        self.0.read_file(file_path)
+    }
+
+    fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        self.0.directory_exists(path)
+    }
+
+    fn file_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        self.0.file_exists(path)
+    }
+
+    fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
+        self.0.enumerate_files(directory)
+    }
+
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError> {
+        self.0.list_files()
+    }
+}
+
+/// A file found by `Repository::list_files`.
+pub struct FileEntry {
+    pub path: String,
+    /// The blob holding the contents, for Git repositories.
+    id: Option<Oid>,
+}
+
+/// Reads files listed by `Repository::list_files`. Readers are independent of each other and of
+/// the repository, so that files can be read on several threads at once.
+pub trait FileReader {
+    fn read(&self, file: &FileEntry) -> Result<Vec<u8>, MyError>;
+}
+
+/// Called with the paths of files that changed outside of the wiki.
+pub type ChangeCallback = Box<dyn Fn(Vec<String>) + Send>;
+
 pub struct RepoBox(pub Box<dyn Repository + Sync + Send>);
 
 impl Deref for RepoBox {
@@ -71,6 +308,8 @@ fn canonicalize(root_dir: PathBuf, relative_path: &[&str]) -> Result<PathBuf, My
 
 struct FileSystemRepository {
     root_dir: PathBuf,
+    /// Kept so that it keeps watching; it stops when dropped.
+    watcher: Mutex<Option<RecommendedWatcher>>,
 }
 
 impl FileSystemRepository {
@@ -84,6 +323,59 @@ impl Repository for FileSystemRepository {
         RepositoryCapability::empty()
     }
 
+    fn history(&self, _file_path: &[&str]) -> Result<Vec<CommitInfo>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn read_file_at_revision(
+        &self,
+        _file_path: &[&str],
+        _revision: &str,
+    ) -> Result<Vec<u8>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn get_commit(&self, _revision: &str) -> Result<CommitInfo, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn head_commit(&self) -> Result<CommitInfo, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn branches(&self) -> Result<Vec<String>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn current_branch(&self) -> Option<String> {
+        None
+    }
+
+    fn set_branch(&self, _name: &str) -> Result<(), MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn sync(&self, _remote: &str, _author: Option<&Author>) -> Result<SyncResult, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn changed_files(&self, _from: &str, _to: &str) -> Result<Vec<String>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn blame(&self, _file_path: &[&str]) -> Result<Vec<BlameHunk>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn diff_file(
+        &self,
+        _file_path: &[&str],
+        _from: &str,
+        _to: &str,
+    ) -> Result<Vec<DiffHunk>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
     fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
         let path = canonicalize(self.root_dir.clone(), file_path)?;
         let mut f = std::fs::File::open(path)?;
@@ -92,17 +384,66 @@ impl Repository for FileSystemRepository {
         Ok(buf)
     }
 
-    fn write_file(&self, file_path: &[&str], _message: &str, content: &str) -> Result<(), MyError> {
+    fn write_file(
+        &self,
+        file_path: &[&str],
+        _message: &str,
+        content: &[u8],
+        _author: Option<&Author>,
+    ) -> Result<(), MyError> {
         let path = self.canonicalize_path(file_path)?;
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
         let mut f = std::fs::File::create(path)?;
-        f.write_all(content.as_bytes())?;
+        f.write_all(content)?;
         f.flush()?;
         Ok(())
     }
 
+    fn write_file_on(
+        &self,
+        _file_path: &[&str],
+        _message: &str,
+        _content: &[u8],
+        _parent: &str,
+        _author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+
+    fn rename_file(
+        &self,
+        old_path: &[&str],
+        new_path: &[&str],
+        message: &str,
+        updated_files: &[UpdatedFile],
+        _parent: Option<&str>,
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let old_path = self.canonicalize_path(old_path)?;
+        let new_path = self.canonicalize_path(new_path)?;
+        if let Some(parent) = new_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(old_path, new_path)?;
+        for file in updated_files {
+            self.write_file(&file.path(), message, file.content.as_bytes(), author)?;
+        }
+        Ok(())
+    }
+
+    fn delete_file(
+        &self,
+        file_path: &[&str],
+        _message: &str,
+        _author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let path = self.canonicalize_path(file_path)?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
     // TODO: consider if this should return error for anything
     fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
         match self.canonicalize_path(path) {
@@ -144,19 +485,308 @@ impl Repository for FileSystemRepository {
             })
             .collect())
     }
+
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError> {
+        let mut ret = Vec::new();
+        let mut dirs = vec![Vec::new()];
+        while let Some(dir) = dirs.pop() {
+            let dir_refs: Vec<&str> = dir.iter().map(|s: &String| s.as_str()).collect();
+            for item in self.enumerate_files(&dir_refs)? {
+                match item {
+                    RepositoryItem::Directory(name) => {
+                        let mut path = dir.clone();
+                        path.push(name);
+                        dirs.push(path);
+                    }
+                    RepositoryItem::File(name) => {
+                        let mut path = dir_refs.clone();
+                        path.push(&name);
+                        ret.push(FileEntry {
+                            path: path.join("/"),
+                            id: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn file_reader(&self) -> Result<Box<dyn FileReader>, MyError> {
+        Ok(Box::new(FileSystemReader {
+            root_dir: self.root_dir.clone(),
+        }))
+    }
+
+    fn watch(&self, on_change: ChangeCallback) -> Result<(), MyError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&self.root_dir, RecursiveMode::Recursive)?;
+        *self.watcher.lock().unwrap() = Some(watcher);
+
+        let root_dir = self.root_dir.clone();
+        std::thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                // Editors often save a file in several steps, so wait for them to finish.
+                std::thread::sleep(WATCH_DEBOUNCE);
+                let mut changed = Vec::new();
+                for event in std::iter::once(event).chain(receiver.try_iter()) {
+                    let Ok(event) = event else {
+                        continue;
+                    };
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+                    for path in event.paths {
+                        if let Some(path) = wiki_path(&root_dir, &path) {
+                            if !changed.contains(&path) {
+                                changed.push(path);
+                            }
+                        }
+                    }
+                }
+                if !changed.is_empty() {
+                    on_change(changed);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+        Ok(Box::new(LiveSnapshot(self)))
+    }
+
+    fn snapshot_at(&self, _revision: &str) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+        Err(MyError::UnsupportedOperation)
+    }
+}
+
+struct FileSystemReader {
+    root_dir: PathBuf,
+}
+
+impl FileReader for FileSystemReader {
+    fn read(&self, file: &FileEntry) -> Result<Vec<u8>, MyError> {
+        let path: Vec<&str> = file.path.split('/').collect();
+        Ok(std::fs::read(canonicalize(self.root_dir.clone(), &path)?)?)
+    }
+}
+
+/// How long to wait for more file system events before reporting a change.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How often to check whether the served branch moved.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Converts a path on disk to the path of a file in the wiki, skipping hidden files.
+fn wiki_path(root_dir: &Path, path: &Path) -> Option<String> {
+    let mut ret = Vec::new();
+    for component in path.strip_prefix(root_dir).ok()? {
+        let component = component.to_str()?;
+        if !path_element_ok(component) {
+            return None;
+        }
+        ret.push(component);
+    }
+    if ret.is_empty() {
+        None
+    } else {
+        Some(ret.join("/"))
+    }
+}
+
+/// Handles to a Git repository, lent to one thread at a time. A `git2::Repository` cannot be
+/// used from several threads at once, so each concurrent request gets its own.
+struct HandlePool {
+    git_dir: PathBuf,
+    idle: Mutex<Vec<git2::Repository>>,
+}
+
+impl HandlePool {
+    fn get(&self) -> Result<PooledHandle<'_>, MyError> {
+        let repo = match self.idle.lock().unwrap().pop() {
+            Some(repo) => repo,
+            None => git2::Repository::open(&self.git_dir)?,
+        };
+        Ok(PooledHandle {
+            pool: self,
+            repo: Some(repo),
+        })
+    }
+}
+
+/// A handle borrowed from a `HandlePool`, which goes back to the pool when dropped.
+struct PooledHandle<'a> {
+    pool: &'a HandlePool,
+    repo: Option<git2::Repository>,
+}
+
+impl Deref for PooledHandle<'_> {
+    type Target = git2::Repository;
+
+    fn deref(&self) -> &Self::Target {
+        self.repo.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledHandle<'_> {
+    fn drop(&mut self) {
+        if let Some(repo) = self.repo.take() {
+            self.pool.idle.lock().unwrap().push(repo);
+        }
+    }
 }
 
 struct GitRepository {
-    path: PathBuf,
-    repo: Mutex<git2::Repository>,
+    handles: HandlePool,
+    /// Held while committing or moving branches, so that concurrent changes do not overwrite
+    /// each other. Reads do not need it.
+    write_lock: Mutex<()>,
+    /// The full name of the reference being served, or `HEAD` to follow whatever is checked out.
+    /// Shared with the thread watching for new commits.
+    reference: Arc<RwLock<String>>,
+}
+
+impl GitRepository {
+    fn reference(&self) -> String {
+        self.reference.read().unwrap().clone()
+    }
+
+    fn handle(&self) -> Result<PooledHandle<'_>, MyError> {
+        self.handles.get()
+    }
+}
+
+/// Reads blobs using its own handle to the repository.
+struct GitReader {
+    repo: git2::Repository,
+}
+
+impl FileReader for GitReader {
+    fn read(&self, file: &FileEntry) -> Result<Vec<u8>, MyError> {
+        let id = file.id.ok_or(MyError::InvalidPath)?;
+        Ok(self.repo.find_blob(id)?.content().to_vec())
+    }
+}
+
+/// The tree of one commit, read through a handle kept for as long as the snapshot lives.
+struct GitSnapshot<'a> {
+    repo: PooledHandle<'a>,
+    commit_id: String,
+    tree: Oid,
+}
+
+impl GitSnapshot<'_> {
+    fn tree(&self) -> Result<git2::Tree<'_>, MyError> {
+        Ok(self.repo.find_tree(self.tree)?)
+    }
+}
+
+impl RepositorySnapshot for GitSnapshot<'_> {
+    fn commit_id(&self) -> Option<&str> {
+        Some(&self.commit_id)
+    }
+
+    fn file_id(&self, file_path: &[&str]) -> Option<String> {
+        let entry = self
+            .tree()
+            .ok()?
+            .get_path(Path::new(&file_path.join("/")))
+            .ok()?;
+        (entry.kind() == Some(ObjectType::Blob)).then(|| entry.id().to_string())
+    }
+
+    fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
+        read_git_file(&self.repo, self.tree()?, file_path)
+    }
+
+    fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        Ok(get_git_dir(&self.repo, self.tree()?, path).is_ok())
+    }
+
+    fn file_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        let (filename, file_paths) = match path.split_last() {
+            Some(tup) => tup,
+            None => {
+                return Err(MyError::InvalidPath);
+            }
+        };
+
+        let root = match get_git_dir(&self.repo, self.tree()?, file_paths) {
+            Ok(tree) => tree,
+            Err(_) => return Ok(false),
+        };
+
+        let file_obj = match root.get_name(filename) {
+            Some(te) => te.to_object(&self.repo)?,
+            None => {
+                return Ok(false);
+            }
+        };
+
+        Ok(file_obj.as_blob().is_some())
+    }
+
+    fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
+        let tree = get_git_dir(&self.repo, self.tree()?, directory)?;
+        Ok(tree
+            .into_iter()
+            .filter_map(|te| match te.kind() {
+                Some(ObjectType::Blob) => Some(RepositoryItem::File(te.name().unwrap().to_owned())),
+                Some(ObjectType::Tree) => {
+                    Some(RepositoryItem::Directory(te.name().unwrap().to_owned()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError> {
+        list_tree_files(&self.tree()?)
+    }
+}
+
+/// Lists every file in a tree and its subtrees.
+fn list_tree_files(tree: &git2::Tree) -> Result<Vec<FileEntry>, MyError> {
+    let mut ret = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            if let Some(name) = entry.name() {
+                ret.push(FileEntry {
+                    path: format!("{}{}", dir, name),
+                    id: Some(entry.id()),
+                });
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(ret)
+}
+
+fn head_tree<'repo>(
+    repo: &'repo git2::Repository,
+    reference: &str,
+) -> Result<git2::Tree<'repo>, MyError> {
+    Ok(repo.find_reference(reference)?.peel_to_tree()?)
+}
+
+fn find_commit<'repo>(
+    repo: &'repo git2::Repository,
+    revision: &str,
+) -> Result<git2::Commit<'repo>, MyError> {
+    repo.revparse_single(revision)
+        .and_then(|obj| obj.peel_to_commit())
+        .map_err(|_| MyError::RevisionNotFound {
+            revision: revision.to_owned(),
+        })
 }
 
 fn get_git_dir<'repo>(
-    repo: &'repo std::sync::MutexGuard<git2::Repository>,
+    repo: &'repo git2::Repository,
+    mut root: git2::Tree<'repo>,
     file_paths: &[&str],
 ) -> Result<git2::Tree<'repo>, MyError> {
-    let head = repo.head()?;
-    let mut root = head.peel_to_tree()?;
     for path in file_paths {
         let obj = match root.get_name(path) {
             Some(te) => te.to_object(repo)?,
@@ -174,113 +804,766 @@ fn get_git_dir<'repo>(
     Ok(root)
 }
 
+fn read_git_file(
+    repo: &git2::Repository,
+    root: git2::Tree,
+    file_path: &[&str],
+) -> Result<Vec<u8>, MyError> {
+    let (filename, file_paths) = match file_path.split_last() {
+        Some(tup) => tup,
+        None => {
+            return Err(MyError::InvalidPath);
+        }
+    };
+
+    let root = get_git_dir(repo, root, file_paths)?;
+
+    let file_obj = match root.get_name(filename) {
+        Some(te) => te.to_object(repo)?,
+        None => {
+            return Err(MyError::InvalidPath);
+        }
+    };
+
+    match file_obj.as_blob() {
+        Some(b) => Ok(b.content().to_owned()),
+        None => Err(MyError::InvalidPath),
+    }
+}
+
+/// Gets the ID of the object at `path` in the commit's tree, if there is one.
+fn get_entry_id(commit: &git2::Commit, path: &Path) -> Result<Option<Oid>, MyError> {
+    match commit.tree()?.get_path(path) {
+        Ok(te) => Ok(Some(te.id())),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn commit_info(commit: &git2::Commit) -> Result<CommitInfo, MyError> {
+    let author = commit.author();
+    let when = author.when();
+    let offset =
+        UtcOffset::from_whole_seconds(when.offset_minutes() * 60).unwrap_or(UtcOffset::UTC);
+    let time = OffsetDateTime::from_unix_timestamp(when.seconds())
+        .map_err(|_| MyError::InvalidCommitTime)?
+        .to_offset(offset);
+    let short_id = commit.as_object().short_id()?;
+    Ok(CommitInfo {
+        id: commit.id().to_string(),
+        short_id: short_id.as_str().unwrap_or_default().to_owned(),
+        author_name: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+        author_email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+        time,
+        summary: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).into_owned(),
+    })
+}
+
+/// Returns the ID of a new tree that is `tree` with the blob at `path` replaced by `blob`, or
+/// removed if `blob` is `None`. Returns `None` if the resulting tree is empty.
+fn update_tree(
+    repo: &git2::Repository,
+    tree: Option<&git2::Tree>,
+    path: &[&str],
+    blob: Option<Oid>,
+) -> Result<Option<Oid>, MyError> {
+    let (name, rest) = path.split_first().ok_or(MyError::InvalidPath)?;
+    let mut builder = repo.treebuilder(tree)?;
+    let existing = builder
+        .get(name)?
+        .map(|te| (te.id(), te.kind(), te.filemode()));
+
+    let (new_id, mode) = if rest.is_empty() {
+        let mode = match existing {
+            Some((_, Some(ObjectType::Blob), mode)) => mode,
+            Some((_, Some(ObjectType::Tree), _)) => return Err(MyError::PathAlreadyExists),
+            _ => git2::FileMode::Blob.into(),
+        };
+        (blob, mode)
+    } else {
+        let subtree = match existing {
+            Some((id, Some(ObjectType::Tree), _)) => Some(repo.find_tree(id)?),
+            Some(_) => return Err(MyError::PathAlreadyExists),
+            None => None,
+        };
+        let new_id = update_tree(repo, subtree.as_ref(), rest, blob)?;
+        (new_id, git2::FileMode::Tree.into())
+    };
+
+    match new_id {
+        Some(id) => {
+            builder.insert(name, id, mode)?;
+        }
+        None if existing.is_some() => builder.remove(name)?,
+        None => {}
+    }
+    if builder.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(builder.write()?))
+    }
+}
+
+/// The signature to commit with: `author` if given, otherwise the user configured in Git.
+fn signature(
+    repo: &git2::Repository,
+    author: Option<&Author>,
+) -> Result<git2::Signature<'static>, MyError> {
+    match author {
+        Some(author) => {
+            git2::Signature::now(&author.name, &author.email).map_err(|_| MyError::InvalidAuthor)
+        }
+        None => repo.signature().map_err(|_| MyError::NoCommitAuthor),
+    }
+}
+
+/// Commits new contents for files on top of `reference`. A `None` blob deletes the file. If
+/// `parent` is given, fails with `MyError::HeadMoved` unless `reference` points to that commit.
+///
+/// The trees are built directly in the object database, so this works in bare repositories. When
+/// `reference` is the branch checked out in the working directory, the changed files are checked
+/// out as well, except for those with uncommitted changes, which are left alone.
+fn commit_changes(
+    repo: &git2::Repository,
+    reference: &str,
+    message: &str,
+    author: Option<&Author>,
+    changes: &[(&[&str], Option<Oid>)],
+    parent: Option<&str>,
+) -> Result<(), MyError> {
+    let sig = signature(repo, author)?;
+    let head = repo.find_reference(reference)?;
+    let head_commit = head.peel_to_commit()?;
+    if parent.is_some_and(|parent| head_commit.id().to_string() != parent) {
+        return Err(MyError::HeadMoved);
+    }
+
+    let mut root = Some(head_commit.tree_id());
+    for (path, blob) in changes {
+        if path.is_empty() || !path.iter().all(|p| path_element_ok(p)) {
+            return Err(MyError::InvalidPath);
+        }
+        let tree = root.map(|id| repo.find_tree(id)).transpose()?;
+        root = update_tree(repo, tree.as_ref(), path, *blob)?;
+    }
+    let root = match root {
+        Some(id) => id,
+        None => repo.treebuilder(None)?.write()?,
+    };
+    let tree = repo.find_tree(root)?;
+
+    let unchanged = unchanged_paths(repo, &head, changes.iter().map(|(path, _)| path.join("/")))?;
+    // Moving the branch fails if something outside of the wiki moved it in the meantime.
+    match repo.commit(head.name(), &sig, &sig, message, &tree, &[&head_commit]) {
+        Err(err) if parent.is_some() && err.code() == git2::ErrorCode::Modified => {
+            return Err(MyError::HeadMoved);
+        }
+        res => res?,
+    };
+    checkout_paths(repo, &tree, &unchanged)
+}
+
+/// The `paths` without uncommitted changes, if `reference` is the branch checked out in the
+/// working directory. These can be checked out again once the branch moves.
+///
+/// Call this before moving the branch, so that the files are compared against the commit they
+/// were checked out from.
+fn unchanged_paths(
+    repo: &git2::Repository,
+    reference: &git2::Reference,
+    paths: impl IntoIterator<Item = String>,
+) -> Result<Vec<String>, MyError> {
+    let checked_out = !repo.is_bare()
+        && match (reference.resolve(), repo.head()) {
+            (Ok(target), Ok(checked_out)) => target.name() == checked_out.name(),
+            _ => false,
+        };
+    if !checked_out {
+        return Ok(Vec::new());
+    }
+
+    let mut ret = Vec::new();
+    for path in paths {
+        match repo.status_file(Path::new(&path)) {
+            Ok(status) if status.is_empty() => ret.push(path),
+            Ok(_) => {}
+            // Neither committed nor in the working directory.
+            Err(err) if err.code() == git2::ErrorCode::NotFound => ret.push(path),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(ret)
+}
+
+/// Updates `paths`, as returned by `unchanged_paths`, in the working directory to match `tree`.
+///
+/// Call this only once the branch has moved to `tree`, so that nothing is checked out for a commit
+/// that never landed.
+fn checkout_paths(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    paths: &[String],
+) -> Result<(), MyError> {
+    // Without any paths, the whole tree would be checked out.
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout
+        .force()
+        .remove_untracked(true)
+        .disable_pathspec_match(true);
+    for path in paths {
+        checkout.path(path);
+    }
+    repo.checkout_tree(tree.as_object(), Some(&mut checkout))?;
+    Ok(())
+}
+
+/// Lists the paths of the files that differ between two trees.
+fn changed_paths(
+    repo: &git2::Repository,
+    old_tree: &git2::Tree,
+    new_tree: &git2::Tree,
+) -> Result<Vec<String>, MyError> {
+    let diff = repo.diff_tree_to_tree(Some(old_tree), Some(new_tree), None)?;
+    let mut ret = Vec::new();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path().and_then(|p| p.to_str()) {
+                if !ret.iter().any(|p| p == path) {
+                    ret.push(path.to_owned());
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn remote_callbacks<'a>() -> git2::RemoteCallbacks<'a> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking as long as credentials are returned, so give up eventually.
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+            let config = git2::Config::open_default()?;
+            git2::Cred::credential_helper(&config, url, username)
+        } else {
+            git2::Cred::default()
+        }
+    });
+    callbacks
+}
+
+/// Brings the changes from `upstream` into the branch `reference` points to, by fast-forwarding
+/// if possible and with a merge commit otherwise.
+fn pull(
+    repo: &git2::Repository,
+    reference: &str,
+    upstream: &git2::Reference,
+    author: Option<&Author>,
+) -> Result<(PullResult, Vec<String>), MyError> {
+    let head = repo.find_reference(reference)?.resolve()?;
+    let local = head.peel_to_commit()?;
+    let upstream_name = upstream.shorthand().unwrap_or_default().to_owned();
+    let upstream = upstream.peel_to_commit()?;
+    if local.id() == upstream.id() || repo.graph_descendant_of(local.id(), upstream.id())? {
+        return Ok((PullResult::UpToDate, Vec::new()));
+    }
+
+    if repo.graph_descendant_of(upstream.id(), local.id())? {
+        let tree = upstream.tree()?;
+        let changed = changed_paths(repo, &local.tree()?, &tree)?;
+        let unchanged = unchanged_paths(repo, &head, changed.iter().cloned())?;
+        repo.reference(
+            head.name().ok_or(MyError::InvalidPath)?,
+            upstream.id(),
+            true,
+            &format!("sync: fast-forward to {}", upstream_name),
+        )?;
+        checkout_paths(repo, &tree, &unchanged)?;
+        return Ok((PullResult::FastForwarded, changed));
+    }
+
+    let mut index = repo.merge_commits(&local, &upstream, None)?;
+    if index.has_conflicts() {
+        let mut paths = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+            if let Some(entry) = entry {
+                paths.push(String::from_utf8_lossy(&entry.path).into_owned());
+            }
+        }
+        return Ok((PullResult::Conflicted(paths), Vec::new()));
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let changed = changed_paths(repo, &local.tree()?, &tree)?;
+    let unchanged = unchanged_paths(repo, &head, changed.iter().cloned())?;
+    let sig = signature(repo, author)?;
+    let message = format!(
+        "Merge {} into {}",
+        upstream_name,
+        head.shorthand().unwrap_or_default()
+    );
+    repo.commit(
+        head.name(),
+        &sig,
+        &sig,
+        &message,
+        &tree,
+        &[&local, &upstream],
+    )?;
+    checkout_paths(repo, &tree, &unchanged)?;
+    Ok((PullResult::Merged, changed))
+}
+
 impl Repository for GitRepository {
     fn capabilities(&self) -> RepositoryCapability {
         RepositoryCapability::SUPPORTS_EDIT_MESSAGE
+            | RepositoryCapability::SUPPORTS_HISTORY
+            | RepositoryCapability::SUPPORTS_BRANCHES
+    }
+
+    fn history(&self, file_path: &[&str]) -> Result<Vec<CommitInfo>, MyError> {
+        if file_path.is_empty() || !file_path.iter().all(|p| path_element_ok(p)) {
+            return Err(MyError::InvalidPath);
+        }
+        let path: PathBuf = file_path.iter().collect();
+
+        let repo = self.handle()?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        revwalk.push_ref(&self.reference())?;
+
+        let mut ret = Vec::new();
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            let id = get_entry_id(&commit, &path)?;
+            if id.is_none() && commit.parent_count() == 0 {
+                continue;
+            }
+            // Like `git log`, only show a commit if it differs from all of its parents.
+            // A merge that took the file from one side unchanged is not interesting.
+            let mut changed = true;
+            for parent in commit.parents() {
+                if get_entry_id(&parent, &path)? == id {
+                    changed = false;
+                    break;
+                }
+            }
+            if changed {
+                ret.push(commit_info(&commit)?);
+            }
+        }
+        Ok(ret)
     }
 
     fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
-        let (filename, file_paths) = match file_path.split_last() {
-            Some(tup) => tup,
-            None => {
-                return Err(MyError::InvalidPath);
+        self.snapshot()?.read_file(file_path)
+    }
+
+    fn read_file_at_revision(
+        &self,
+        file_path: &[&str],
+        revision: &str,
+    ) -> Result<Vec<u8>, MyError> {
+        let repo = self.handle()?;
+        let root = find_commit(&repo, revision)?.tree()?;
+        read_git_file(&repo, root, file_path)
+    }
+
+    fn get_commit(&self, revision: &str) -> Result<CommitInfo, MyError> {
+        let repo = self.handle()?;
+        let commit = find_commit(&repo, revision)?;
+        commit_info(&commit)
+    }
+
+    fn head_commit(&self) -> Result<CommitInfo, MyError> {
+        let repo = self.handle()?;
+        let commit = repo.find_reference(&self.reference())?.peel_to_commit()?;
+        commit_info(&commit)
+    }
+
+    fn branches(&self) -> Result<Vec<String>, MyError> {
+        let repo = self.handle()?;
+        let mut ret = Vec::new();
+        for branch in repo.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            if let Some(name) = branch.name()? {
+                ret.push(name.to_owned());
             }
-        };
+        }
+        ret.sort();
+        Ok(ret)
+    }
 
-        let repo = self.repo.lock().unwrap();
-        let root = get_git_dir(&repo, file_paths)?;
+    fn current_branch(&self) -> Option<String> {
+        let repo = self.handle().ok()?;
+        let reference = repo.find_reference(&self.reference()).ok()?;
+        let reference = reference.resolve().ok()?;
+        if reference.is_branch() {
+            reference.shorthand().map(|s| s.to_owned())
+        } else {
+            None
+        }
+    }
 
-        let file_obj = match root.get_name(filename) {
-            Some(te) => te.to_object(&repo)?,
-            None => {
-                return Err(MyError::InvalidPath);
+    fn set_branch(&self, name: &str) -> Result<(), MyError> {
+        let repo = self.handle()?;
+        let short_name = name.strip_prefix("refs/heads/").unwrap_or(name);
+        let branch = repo
+            .find_branch(short_name, git2::BranchType::Local)
+            .map_err(|_| MyError::BranchNotFound {
+                branch: name.to_owned(),
+            })?;
+        let reference = branch.get().name().ok_or(MyError::InvalidPath)?;
+        *self.reference.write().unwrap() = reference.to_owned();
+        Ok(())
+    }
+
+    fn sync(&self, remote_name: &str, author: Option<&Author>) -> Result<SyncResult, MyError> {
+        let (git_dir, branch) = {
+            let repo = self.handle()?;
+            let head = repo.find_reference(&self.reference())?.resolve()?;
+            if !head.is_branch() {
+                return Err(MyError::NotOnBranch);
             }
+            let branch = head.shorthand().ok_or(MyError::NotOnBranch)?.to_owned();
+            (repo.path().to_owned(), branch)
         };
+        let local_ref = format!("refs/heads/{}", branch);
+        let tracking_ref = format!("refs/remotes/{}/{}", remote_name, branch);
+
+        // Talk to the remote using a separate handle, so that the wiki can still be read while
+        // waiting on the network.
+        let network_repo = git2::Repository::open(&git_dir)?;
+        let mut remote = network_repo.find_remote(remote_name)?;
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks());
+        remote.fetch(
+            &[format!("+{}:{}", local_ref, tracking_ref)],
+            Some(&mut fetch_options),
+            None,
+        )?;
 
-        match file_obj.as_blob() {
-            Some(b) => Ok(b.content().to_owned()),
-            None => Err(MyError::InvalidPath),
+        let (pull, changed_files, needs_push) = {
+            let _writing = self.write_lock.lock().unwrap();
+            let repo = self.handle()?;
+            let (pull, changed_files) = match repo.find_reference(&tracking_ref) {
+                Ok(upstream) => pull(&repo, &self.reference(), &upstream, author)?,
+                // The remote does not have the branch yet.
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                    (PullResult::UpToDate, Vec::new())
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let local = repo.refname_to_id(&local_ref)?;
+            let needs_push = match repo.refname_to_id(&tracking_ref) {
+                Ok(upstream) => upstream != local && !matches!(pull, PullResult::Conflicted(_)),
+                Err(_) => true,
+            };
+            (pull, changed_files, needs_push)
+        };
+
+        if needs_push {
+            let mut rejection = None;
+            {
+                let mut callbacks = remote_callbacks();
+                callbacks.push_update_reference(|_, status| {
+                    rejection = status.map(|s| s.to_owned());
+                    Ok(())
+                });
+                let mut push_options = git2::PushOptions::new();
+                push_options.remote_callbacks(callbacks);
+                remote.push(
+                    &[format!("{}:{}", local_ref, local_ref)],
+                    Some(&mut push_options),
+                )?;
+            }
+            if let Some(message) = rejection {
+                return Err(MyError::PushRejected { message });
+            }
+            // Git does the same after a successful push.
+            let local = network_repo.refname_to_id(&local_ref)?;
+            network_repo.reference(&tracking_ref, local, true, "sync: push")?;
         }
+
+        Ok(SyncResult {
+            pull,
+            changed_files,
+            pushed: needs_push,
+        })
+    }
+
+    fn list_files(&self) -> Result<Vec<FileEntry>, MyError> {
+        self.snapshot()?.list_files()
+    }
+
+    fn file_reader(&self) -> Result<Box<dyn FileReader>, MyError> {
+        let git_dir = self.handles.git_dir.clone();
+        Ok(Box::new(GitReader {
+            repo: git2::Repository::open(git_dir)?,
+        }))
+    }
+
+    fn watch(&self, on_change: ChangeCallback) -> Result<(), MyError> {
+        let git_dir = self.handles.git_dir.clone();
+        let reference = Arc::clone(&self.reference);
+        // Use a separate handle, so that checking for new commits does not wait on requests.
+        let repo = git2::Repository::open(git_dir)?;
+        let head_id = move |repo: &git2::Repository| {
+            let reference = reference.read().unwrap().clone();
+            repo.find_reference(&reference)
+                .and_then(|r| r.peel_to_commit())
+                .map(|commit| commit.id())
+                .ok()
+        };
+        let mut last = head_id(&repo);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let Some(head) = head_id(&repo) else {
+                continue;
+            };
+            if last == Some(head) {
+                continue;
+            }
+            let old_tree = last.and_then(|id| repo.find_commit(id).and_then(|c| c.tree()).ok());
+            let changed = match (old_tree, repo.find_commit(head).and_then(|c| c.tree())) {
+                (Some(old_tree), Ok(new_tree)) => changed_paths(&repo, &old_tree, &new_tree),
+                _ => Ok(Vec::new()),
+            };
+            last = Some(head);
+            match changed {
+                Ok(changed) => on_change(changed),
+                Err(err) => eprintln!("Failed to find the files changed by a commit: {}", err),
+            }
+        });
+        Ok(())
     }
 
-    fn write_file(&self, file_path: &[&str], message: &str, content: &str) -> Result<(), MyError> {
-        if file_path.is_empty() {
+    fn changed_files(&self, from: &str, to: &str) -> Result<Vec<String>, MyError> {
+        let repo = self.handle()?;
+        let from = find_commit(&repo, from)?.tree()?;
+        let to = find_commit(&repo, to)?.tree()?;
+        changed_paths(&repo, &from, &to)
+    }
+
+    fn blame(&self, file_path: &[&str]) -> Result<Vec<BlameHunk>, MyError> {
+        if file_path.is_empty() || !file_path.iter().all(|p| path_element_ok(p)) {
             return Err(MyError::InvalidPath);
         }
+        let path: PathBuf = file_path.iter().collect();
 
-        // Get as many Git objects ready before writing the file.
-        let repo = self.repo.lock().unwrap();
-        let mut index = repo.index()?;
-        let sig = repo.signature()?;
-        let head = repo.head()?;
-        let head_commit = head.peel_to_commit()?;
+        let repo = self.handle()?;
+        let head = repo.find_reference(&self.reference())?.peel_to_commit()?;
+        let content = read_git_file(&repo, head.tree()?, file_path)?;
+        let content = String::from_utf8_lossy(&content);
+        let lines: Vec<&str> = content.lines().collect();
 
-        let path = canonicalize(self.path.clone(), file_path)?;
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let mut opts = git2::BlameOptions::new();
+        opts.newest_commit(head.id());
+        let blame = repo.blame_file(&path, Some(&mut opts))?;
+
+        let mut commits: HashMap<Oid, CommitInfo> = HashMap::new();
+        let mut ret = Vec::with_capacity(blame.len());
+        for hunk in blame.iter() {
+            let commit_id = hunk.final_commit_id();
+            let commit = match commits.get(&commit_id) {
+                Some(commit) => commit.clone(),
+                None => {
+                    let commit = commit_info(&repo.find_commit(commit_id)?)?;
+                    commits.insert(commit_id, commit.clone());
+                    commit
+                }
+            };
+            let start = hunk.final_start_line();
+            let hunk_lines = (start..start + hunk.lines_in_hunk())
+                .map(|lineno| {
+                    let text = lines.get(lineno - 1).copied().unwrap_or_default();
+                    (lineno, text.to_owned())
+                })
+                .collect();
+            ret.push(BlameHunk {
+                commit,
+                lines: hunk_lines,
+            });
         }
+        Ok(ret)
+    }
 
-        // TODO: maybe write the file directly into the index or as a tree.
-        // This would support bare Git repos.
-        std::fs::write(&path, content)?;
+    fn diff_file(
+        &self,
+        file_path: &[&str],
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<DiffHunk>, MyError> {
+        let repo = self.handle()?;
+        let old =
+            read_git_file(&repo, find_commit(&repo, from)?.tree()?, file_path).unwrap_or_default();
+        let new =
+            read_git_file(&repo, find_commit(&repo, to)?.tree()?, file_path).unwrap_or_default();
+        let path: PathBuf = file_path.iter().collect();
+        let patch = git2::Patch::from_buffers(&old, Some(&path), &new, Some(&path), None)?;
 
-        let git_path = path
-            .strip_prefix(self.path.clone())
-            .map_err(|_| MyError::InvalidPath)?;
+        let mut hunks = Vec::with_capacity(patch.num_hunks());
+        for hunk_ndx in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(hunk_ndx)?;
+            let mut lines = Vec::with_capacity(line_count);
+            for line_ndx in 0..line_count {
+                let line = patch.line_in_hunk(hunk_ndx, line_ndx)?;
+                let kind = match line.origin() {
+                    ' ' => DiffLineKind::Context,
+                    '+' => DiffLineKind::Addition,
+                    '-' => DiffLineKind::Deletion,
+                    // Skip the "no newline at end of file" markers.
+                    _ => continue,
+                };
+                let content = String::from_utf8_lossy(line.content());
+                lines.push(DiffLine {
+                    kind,
+                    old_lineno: line.old_lineno(),
+                    new_lineno: line.new_lineno(),
+                    content: content.trim_end_matches(['\r', '\n']).to_owned(),
+                });
+            }
+            hunks.push(DiffHunk {
+                header: String::from_utf8_lossy(hunk.header()).trim_end().to_owned(),
+                lines,
+            });
+        }
+        Ok(hunks)
+    }
 
-        index.add_path(git_path)?;
-        index.write()?;
-        let tree = index.write_tree()?;
-        let tree = repo.find_tree(tree)?;
+    fn write_file(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let _writing = self.write_lock.lock().unwrap();
+        let repo = self.handle()?;
+        let blob = repo.blob(content)?;
+        commit_changes(
+            &repo,
+            &self.reference(),
+            message,
+            author,
+            &[(file_path, Some(blob))],
+            None,
+        )
+    }
 
-        repo.commit(head.name(), &sig, &sig, message, &tree, &[&head_commit])?;
+    fn write_file_on(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        parent: &str,
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let _writing = self.write_lock.lock().unwrap();
+        let repo = self.handle()?;
+        let blob = repo.blob(content)?;
+        commit_changes(
+            &repo,
+            &self.reference(),
+            message,
+            author,
+            &[(file_path, Some(blob))],
+            Some(parent),
+        )
+    }
 
-        Ok(())
+    fn rename_file(
+        &self,
+        old_path: &[&str],
+        new_path: &[&str],
+        message: &str,
+        updated_files: &[UpdatedFile],
+        parent: Option<&str>,
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        if old_path.is_empty() || new_path.is_empty() {
+            return Err(MyError::InvalidPath);
+        }
+
+        let _writing = self.write_lock.lock().unwrap();
+        let repo = self.handle()?;
+        let old_file: PathBuf = old_path.iter().collect();
+        let old_blob = head_tree(&repo, &self.reference())?
+            .get_path(&old_file)
+            .map_err(|_| MyError::InvalidPath)?
+            .id();
+
+        let updated_paths: Vec<Vec<&str>> = updated_files.iter().map(|f| f.path()).collect();
+        let mut changes = vec![(old_path, None), (new_path, Some(old_blob))];
+        for (file, path) in updated_files.iter().zip(&updated_paths) {
+            let blob = repo.blob(file.content.as_bytes())?;
+            changes.push((path.as_slice(), Some(blob)));
+        }
+        commit_changes(&repo, &self.reference(), message, author, &changes, parent)
+    }
+
+    fn delete_file(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let _writing = self.write_lock.lock().unwrap();
+        let repo = self.handle()?;
+        commit_changes(
+            &repo,
+            &self.reference(),
+            message,
+            author,
+            &[(file_path, None)],
+            None,
+        )
     }
 
     fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
-        let repo = self.repo.lock().unwrap();
-        let ret = get_git_dir(&repo, path).is_ok();
-        Ok(ret)
+        self.snapshot()?.directory_exists(path)
     }
 
     fn file_exists(&self, path: &[&str]) -> Result<bool, MyError> {
-        let (filename, file_paths) = match path.split_last() {
-            Some(tup) => tup,
-            None => {
-                return Err(MyError::InvalidPath);
-            }
-        };
+        self.snapshot()?.file_exists(path)
+    }
 
-        let repo = self.repo.lock().unwrap();
-        let root = match get_git_dir(&repo, file_paths) {
-            Ok(tree) => tree,
-            Err(_) => return Ok(false),
-        };
+    fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
+        self.snapshot()?.enumerate_files(directory)
+    }
 
-        let file_obj = match root.get_name(filename) {
-            Some(te) => te.to_object(&repo)?,
-            None => {
-                return Ok(false);
-            }
+    fn snapshot(&self) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+        let repo = self.handle()?;
+        let (commit_id, tree) = {
+            let commit = repo.find_reference(&self.reference())?.peel_to_commit()?;
+            (commit.id().to_string(), commit.tree_id())
         };
-
-        Ok(file_obj.as_blob().is_some())
+        Ok(Box::new(GitSnapshot {
+            repo,
+            commit_id,
+            tree,
+        }))
     }
 
-    fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
-        let repo = self.repo.lock().unwrap();
-        let tree = get_git_dir(&repo, directory)?;
-        Ok(tree
-            .into_iter()
-            .filter_map(|te| match te.kind() {
-                Some(ObjectType::Blob) => Some(RepositoryItem::File(te.name().unwrap().to_owned())),
-                Some(ObjectType::Tree) => {
-                    Some(RepositoryItem::Directory(te.name().unwrap().to_owned()))
-                }
-                _ => None,
-            })
-            .collect())
+    fn snapshot_at(&self, revision: &str) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+        let repo = self.handle()?;
+        let (commit_id, tree) = {
+            let commit = find_commit(&repo, revision)?;
+            (commit.id().to_string(), commit.tree_id())
+        };
+        Ok(Box::new(GitSnapshot {
+            repo,
+            commit_id,
+            tree,
+        }))
     }
 }
 
@@ -291,12 +1574,13 @@ pub fn create_git_repository(dir_path: PathBuf) -> Result<RepoBox, MyError> {
             return Err(MyError::GitRepoDoesFailedToOpen { err });
         }
     };
-    if repo.is_bare() {
-        return Err(MyError::BareGitRepo);
-    }
     Ok(RepoBox(Box::new(GitRepository {
-        path: dir_path,
-        repo: Mutex::new(repo),
+        handles: HandlePool {
+            git_dir: repo.path().to_owned(),
+            idle: Mutex::new(vec![repo]),
+        },
+        write_lock: Mutex::new(()),
+        reference: Arc::new(RwLock::new("HEAD".to_owned())),
     })))
 }
 
@@ -311,8 +1595,390 @@ pub fn create_repository(use_fs: bool, dir_path: PathBuf) -> Result<RepoBox, MyE
         return Err(MyError::GitRepoDoesNotExist { path: root_dir });
     }
     if use_fs {
-        Ok(RepoBox(Box::new(FileSystemRepository { root_dir })))
+        Ok(RepoBox(Box::new(FileSystemRepository {
+            root_dir,
+            watcher: Mutex::new(None),
+        })))
     } else {
         create_git_repository(root_dir)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wiki_path() {
+        let root = Path::new("/wiki");
+        assert_eq!(
+            Some("dir/page.md".to_owned()),
+            wiki_path(root, Path::new("/wiki/dir/page.md"))
+        );
+        assert_eq!(None, wiki_path(root, Path::new("/wiki/.git/HEAD")));
+        assert_eq!(None, wiki_path(root, Path::new("/wiki")));
+        assert_eq!(None, wiki_path(root, Path::new("/elsewhere/page.md")));
+    }
+
+    /// Creates a Git repository with one commit containing `README.md`.
+    fn init_repo(dir: &Path, bare: bool) -> RepoBox {
+        let repo = if bare {
+            git2::Repository::init_bare(dir).unwrap()
+        } else {
+            git2::Repository::init(dir).unwrap()
+        };
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Tester").unwrap();
+        config.set_str("user.email", "tester@example.com").unwrap();
+
+        let blob = repo.blob(b"# Readme\n").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder
+            .insert("README.md", blob, git2::FileMode::Blob.into())
+            .unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+            .unwrap();
+        if !bare {
+            repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+                .unwrap();
+        }
+
+        create_git_repository(dir.to_path_buf()).unwrap()
+    }
+
+    /// Clones `remote` into a bare repository, with `origin` pointing back to it.
+    fn clone_repo(remote: &Path, dir: &Path) -> RepoBox {
+        let repo = git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(remote.to_str().unwrap(), dir)
+            .unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Other").unwrap();
+        config.set_str("user.email", "other@example.com").unwrap();
+        create_git_repository(dir.to_path_buf()).unwrap()
+    }
+
+    #[test]
+    fn test_list_and_read_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        repo.write_file(&["docs", "page.md"], "Add page", b"hello", None)
+            .unwrap();
+
+        let mut files = repo.list_files().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(vec!["README.md", "docs/page.md"], paths);
+        let reader = repo.file_reader().unwrap();
+        assert_eq!(b"hello".to_vec(), reader.read(&files[1]).unwrap());
+    }
+
+    #[test]
+    fn test_write_file_on_moved_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        let parent = repo.head_commit().unwrap().id;
+        repo.write_file_on(&["page.md"], "Add page", b"one", &parent, None)
+            .unwrap();
+
+        let result = repo.write_file_on(&["page.md"], "Edit page", b"two", &parent, None);
+        assert!(matches!(result, Err(MyError::HeadMoved)));
+        assert_eq!(b"one".to_vec(), repo.read_file(&["page.md"]).unwrap());
+    }
+
+    #[test]
+    fn test_rename_file_on_moved_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        repo.write_file(&["page.md"], "Add page", b"one", None)
+            .unwrap();
+        let parent = repo.head_commit().unwrap().id;
+        repo.write_file(&["other.md"], "Edit other", b"new", None)
+            .unwrap();
+
+        let updated = [UpdatedFile {
+            path: vec!["other.md".to_owned()],
+            content: "stale".to_owned(),
+        }];
+        let result = repo.rename_file(
+            &["page.md"],
+            &["moved.md"],
+            "Move page",
+            &updated,
+            Some(&parent),
+            None,
+        );
+        assert!(matches!(result, Err(MyError::HeadMoved)));
+        assert!(repo.file_exists(&["page.md"]).unwrap());
+        assert_eq!(b"new".to_vec(), repo.read_file(&["other.md"]).unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        repo.write_file(&["page.md"], "Add page", b"old", None)
+            .unwrap();
+        let head = repo.head_commit().unwrap();
+
+        let snapshot = repo.snapshot().unwrap();
+        repo.write_file(&["page.md"], "Edit page", b"new", None)
+            .unwrap();
+        repo.write_file(&["dir", "other.md"], "Add other", b"other", None)
+            .unwrap();
+
+        assert_eq!(Some(head.id.as_str()), snapshot.commit_id());
+        assert_eq!(b"old".to_vec(), snapshot.read_file(&["page.md"]).unwrap());
+        assert!(!snapshot.directory_exists(&["dir"]).unwrap());
+        assert!(!snapshot.file_exists(&["dir", "other.md"]).unwrap());
+        assert_eq!(2, snapshot.enumerate_files(&[]).unwrap().len());
+        assert_eq!(b"new".to_vec(), repo.read_file(&["page.md"]).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_writes_and_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let repo = &repo;
+                scope.spawn(move || {
+                    let name = format!("page{}.md", thread);
+                    for ndx in 0..5 {
+                        let content = format!("version {}", ndx);
+                        repo.write_file(&[&name], "Edit", content.as_bytes(), None)
+                            .unwrap();
+                        assert_eq!(content.as_bytes(), repo.read_file(&[&name]).unwrap());
+                    }
+                });
+            }
+        });
+        // No commit was lost to another one made at the same time.
+        assert_eq!(5, repo.history(&["page0.md"]).unwrap().len());
+        assert_eq!(5, repo.enumerate_files(&[]).unwrap().len());
+    }
+
+    #[test]
+    fn test_write_to_bare_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+
+        repo.write_file(&["docs", "page.md"], "Add page", b"hello", None)
+            .unwrap();
+        assert_eq!(
+            b"hello".to_vec(),
+            repo.read_file(&["docs", "page.md"]).unwrap()
+        );
+        assert_eq!("Add page", repo.get_commit("HEAD").unwrap().summary);
+
+        repo.rename_file(
+            &["docs", "page.md"],
+            &["other.md"],
+            "Move page",
+            &[],
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(!repo.directory_exists(&["docs"]).unwrap());
+        assert_eq!(b"hello".to_vec(), repo.read_file(&["other.md"]).unwrap());
+
+        repo.delete_file(&["other.md"], "Delete page", None)
+            .unwrap();
+        assert_eq!(
+            vec![RepositoryItem::File("README.md".to_owned())],
+            repo.enumerate_files(&[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_author_from_parts() {
+        let author = Author::from_parts(Some(" Name "), Some("name@example.com"))
+            .unwrap()
+            .unwrap();
+        assert_eq!("Name", author.name);
+        assert_eq!("name@example.com", author.email);
+        assert!(Author::from_parts(None, Some(" ")).unwrap().is_none());
+        assert!(matches!(
+            Author::from_parts(Some("Name"), None),
+            Err(MyError::InvalidAuthor)
+        ));
+        assert!(matches!(
+            Author::from_parts(Some(""), Some("name@example.com")),
+            Err(MyError::InvalidAuthor)
+        ));
+    }
+
+    #[test]
+    fn test_write_as_author() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), true);
+        let author = Author {
+            name: "Someone Else".to_owned(),
+            email: "else@example.com".to_owned(),
+        };
+
+        repo.write_file(&["page.md"], "Add page", b"hello", Some(&author))
+            .unwrap();
+        let commit = repo.head_commit().unwrap();
+        assert_eq!("Someone Else", commit.author_name);
+        assert_eq!("else@example.com", commit.author_email);
+
+        let invalid = Author {
+            name: "<>".to_owned(),
+            email: String::new(),
+        };
+        assert!(matches!(
+            repo.write_file(&["page.md"], "Edit page", b"bye", Some(&invalid)),
+            Err(MyError::InvalidAuthor)
+        ));
+    }
+
+    #[test]
+    fn test_write_to_other_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), false);
+        {
+            let git = git2::Repository::open(dir.path()).unwrap();
+            let head = git.head().unwrap().peel_to_commit().unwrap();
+            git.branch("docs", &head, false).unwrap();
+        }
+        let main_branch = repo.current_branch().unwrap();
+
+        repo.set_branch("docs").unwrap();
+        assert_eq!(Some("docs".to_owned()), repo.current_branch());
+        repo.write_file(&["page.md"], "Add page", b"hello", None)
+            .unwrap();
+        assert!(repo.file_exists(&["page.md"]).unwrap());
+        assert!(!dir.path().join("page.md").exists());
+
+        repo.set_branch(&main_branch).unwrap();
+        assert!(!repo.file_exists(&["page.md"]).unwrap());
+        assert!(matches!(
+            repo.set_branch("missing"),
+            Err(MyError::BranchNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_keeps_uncommitted_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), false);
+        std::fs::write(dir.path().join("README.md"), "local edit").unwrap();
+
+        repo.write_file(&["page.md"], "Add page", b"hello", None)
+            .unwrap();
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(dir.path().join("page.md")).unwrap()
+        );
+
+        repo.write_file(&["README.md"], "Update readme", b"committed", None)
+            .unwrap();
+        assert_eq!(
+            b"committed".to_vec(),
+            repo.read_file(&["README.md"]).unwrap()
+        );
+        assert_eq!(
+            "local edit",
+            std::fs::read_to_string(dir.path().join("README.md")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_working_directory_follows_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path(), false);
+        repo.write_file(&["page.md"], "Add page", b"one", None)
+            .unwrap();
+        assert_eq!(
+            "one",
+            std::fs::read_to_string(dir.path().join("page.md")).unwrap()
+        );
+
+        // A commit that cannot move the branch leaves the working directory alone.
+        let branch = repo.current_branch().unwrap();
+        let lock = dir.path().join(format!(".git/refs/heads/{}.lock", branch));
+        std::fs::write(&lock, "").unwrap();
+        assert!(repo
+            .write_file(&["page.md"], "Edit page", b"two", None)
+            .is_err());
+        assert_eq!(
+            "one",
+            std::fs::read_to_string(dir.path().join("page.md")).unwrap()
+        );
+        std::fs::remove_file(lock).unwrap();
+
+        repo.delete_file(&["page.md"], "Delete page", None).unwrap();
+        assert!(!dir.path().join("page.md").exists());
+    }
+
+    #[test]
+    fn test_sync_pushes_and_pulls() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        git2::Repository::init_bare(dirs[0].path()).unwrap();
+        let repo = init_repo(dirs[1].path(), false);
+        git2::Repository::open(dirs[1].path())
+            .unwrap()
+            .remote("origin", dirs[0].path().to_str().unwrap())
+            .unwrap();
+
+        let result = repo.sync("origin", None).unwrap();
+        assert_eq!(PullResult::UpToDate, result.pull);
+        assert!(result.pushed);
+
+        let other = clone_repo(dirs[0].path(), dirs[2].path());
+        other
+            .write_file(&["page.md"], "Add page", b"hello", None)
+            .unwrap();
+        assert!(other.sync("origin", None).unwrap().pushed);
+
+        let result = repo.sync("origin", None).unwrap();
+        assert_eq!(PullResult::FastForwarded, result.pull);
+        assert_eq!(vec!["page.md".to_owned()], result.changed_files);
+        assert!(!result.pushed);
+        assert_eq!(b"hello".to_vec(), repo.read_file(&["page.md"]).unwrap());
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(dirs[1].path().join("page.md")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sync_merges_and_reports_conflicts() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        init_repo(dirs[0].path(), true);
+        let repo = clone_repo(dirs[0].path(), dirs[1].path());
+        let other = clone_repo(dirs[0].path(), dirs[2].path());
+
+        other
+            .write_file(&["theirs.md"], "Add theirs", b"theirs", None)
+            .unwrap();
+        other.sync("origin", None).unwrap();
+        repo.write_file(&["ours.md"], "Add ours", b"ours", None)
+            .unwrap();
+        let result = repo.sync("origin", None).unwrap();
+        assert_eq!(PullResult::Merged, result.pull);
+        assert_eq!(vec!["theirs.md".to_owned()], result.changed_files);
+        assert!(result.pushed);
+        assert!(repo.file_exists(&["ours.md"]).unwrap());
+        assert!(repo.file_exists(&["theirs.md"]).unwrap());
+
+        other.sync("origin", None).unwrap();
+        other
+            .write_file(&["README.md"], "Edit there", b"there", None)
+            .unwrap();
+        other.sync("origin", None).unwrap();
+        repo.write_file(&["README.md"], "Edit here", b"here", None)
+            .unwrap();
+        let result = repo.sync("origin", None).unwrap();
+        assert_eq!(
+            PullResult::Conflicted(vec!["README.md".to_owned()]),
+            result.pull
+        );
+        assert!(!result.pushed);
+        assert_eq!(b"here".to_vec(), repo.read_file(&["README.md"]).unwrap());
+    }
+}
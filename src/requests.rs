@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 
 use rocket::form::Form;
+use rocket::fs::TempFile;
 use rocket::http::impl_from_uri_param_identity;
 use rocket::http::uri::fmt::Formatter;
 use rocket::http::uri::fmt::Path;
@@ -11,17 +12,27 @@ use rocket::http::ContentType;
 use rocket::request::FromSegments;
 use rocket::response;
 use rocket::response::Responder;
+use rocket::tokio::io::AsyncReadExt;
 use rocket::State;
 use rocket::{Build, Rocket};
 
 use crate::error::MyError;
+use crate::links::relative_link;
+use crate::page::PageLink;
 use crate::repository;
+use crate::repository::Author;
+use crate::repository::CommitInfo;
 use crate::repository::RepositoryCapability;
 use crate::templates;
 use crate::templates::render_search_results;
 use crate::templates::{
-    render_edit_page, render_overview, render_page, render_page_placeholder, Breadcrumb,
+    render_backlinks, render_blame, render_compare, render_delete_page, render_edit_page,
+    render_error, render_history, render_overview, render_page, render_page_placeholder,
+    render_rename_page, render_sync_status, BlameHunkView, BranchSwitcher, Breadcrumb,
+    CompareRevision, DiffHunkView, HistoryEntry, LastSync, RevisionNotice,
 };
+use crate::wiki::EditResult;
+use crate::wiki::Snapshot;
 use crate::wiki::Wiki;
 
 // Most of the time we are returning Page, so it is ok that it is bigger
@@ -154,9 +165,28 @@ impl UriDisplay<Path> for WikiPagePath<'_> {
 
 impl_from_uri_param_identity!([Path] ('r) WikiPagePath<'r>);
 
+/// Ways to tell smeagol who to commit as, shown when it does not know.
+const AUTHOR_HINTS: &[&str] = &[
+    "Set author-name and author-email in smeagol.toml.",
+    "Start smeagol with --author-name and --author-email.",
+    "Set user.name and user.email in the Git config, for example with git config --global user.name.",
+    "Fill in the author on the edit form.",
+];
+
 // TODO: is the an easier way to convert an Error into a 500?
 impl<'r, 'o: 'r> Responder<'r, 'o> for MyError {
-    fn respond_to(self, _request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+        let status = match self {
+            MyError::NoCommitAuthor => Some(rocket::http::Status::InternalServerError),
+            MyError::InvalidAuthor => Some(rocket::http::Status::UnprocessableEntity),
+            _ => None,
+        };
+        if let Some(status) = status {
+            let html = render_error("Cannot commit", &self.to_string(), AUTHOR_HINTS)
+                .map_err(|_| rocket::http::Status::InternalServerError)?;
+            return (status, (ContentType::HTML, html)).respond_to(request);
+        }
+
         let str = format!("server error: {}", self);
         rocket::Response::build()
             .header(ContentType::Plain)
@@ -177,22 +207,44 @@ lazy_static! {
     };
 }
 
+fn check_csrf_token(authenticity_token: &str) -> Result<(), MyError> {
+    if authenticity_token != *CSRF_TOKEN {
+        return Err(MyError::Csrf);
+    }
+    Ok(())
+}
+
 #[derive(FromForm)]
 struct PageEditForm<'r> {
     content: &'r str,
     message: &'r str,
+    /// The commit the edit started from, if the repository has history.
+    base_revision: Option<&'r str>,
+    /// Overrides the configured author of the commit.
+    author_name: Option<&'r str>,
+    author_email: Option<&'r str>,
     authenticity_token: &'r str,
 }
 
+impl PageEditForm<'_> {
+    fn author(&self) -> Result<Option<Author>, MyError> {
+        Author::from_parts(self.author_name, self.author_email)
+    }
+}
+
+#[derive(Responder)]
+enum EditResponder {
+    Redirect(response::Redirect),
+    Conflict(response::status::Conflict<(ContentType, String)>),
+}
+
 fn edit_save_inner(
     path: WikiPagePath,
     content: Form<PageEditForm<'_>>,
     w: &State<Wiki>,
     as_create: bool,
-) -> Result<response::Redirect, MyError> {
-    if content.authenticity_token != *CSRF_TOKEN {
-        return Err(MyError::Csrf);
-    }
+) -> Result<EditResponder, MyError> {
+    check_csrf_token(content.authenticity_token)?;
     let message = if content.message.trim().is_empty() {
         let message = if as_create {
             default_new_message(&path)
@@ -203,8 +255,41 @@ fn edit_save_inner(
     } else {
         Cow::Borrowed(content.message.trim())
     };
-    w.write_file(&path.segments, &message, content.content)?;
-    Ok(response::Redirect::to(uri!(page(path))))
+    match w.save_edit(
+        &path.segments,
+        &message,
+        content.content,
+        content.base_revision,
+        content.author()?.as_ref(),
+    )? {
+        EditResult::Saved => Ok(EditResponder::Redirect(response::Redirect::to(uri!(page(
+            path
+        ))))),
+        EditResult::Conflict {
+            content: merged,
+            head,
+        } => {
+            // The base revision is known to be set, otherwise there would be no conflict.
+            let base_revision = content.base_revision.unwrap_or_default();
+            let hunks = w
+                .diff_file(&path.segments, base_revision, &head.id)?
+                .iter()
+                .map(DiffHunkView::new)
+                .collect();
+            let html = render_editor(
+                &path,
+                w,
+                as_create,
+                content.message,
+                content.author_name.unwrap_or_default(),
+                content.author_email.unwrap_or_default(),
+                &merged,
+                Some(&head.id),
+                Some(hunks),
+            )?;
+            Ok(EditResponder::Conflict(response::status::Conflict(html)))
+        }
+    }
 }
 
 #[post("/edit/<path..>", data = "<content>")]
@@ -212,7 +297,7 @@ fn edit_save(
     path: WikiPagePath,
     content: Form<PageEditForm<'_>>,
     w: &State<Wiki>,
-) -> Result<response::Redirect, MyError> {
+) -> Result<EditResponder, MyError> {
     edit_save_inner(path, content, w, false)
 }
 
@@ -221,54 +306,314 @@ fn new_save(
     path: WikiPagePath,
     content: Form<PageEditForm<'_>>,
     w: &State<Wiki>,
-) -> Result<response::Redirect, MyError> {
+) -> Result<EditResponder, MyError> {
     edit_save_inner(path, content, w, true)
 }
 
-fn default_edit_message(path: &WikiPagePath) -> String {
-    format!("Update {}", path.segments.join("/"))
+#[derive(FromForm)]
+struct RestoreForm<'r> {
+    revision: &'r str,
+    authenticity_token: &'r str,
 }
 
-fn default_new_message(path: &WikiPagePath) -> String {
-    format!("Create {}", path.segments.join("/"))
+fn restore_inner(
+    path: WikiPagePath,
+    form: Form<RestoreForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    w.restore_file(&path.segments, form.revision)?;
+    Ok(response::Redirect::to(uri!(page(path))))
 }
 
-fn edit_view_inner(
+#[post("/restore/<path..>", data = "<form>")]
+fn restore(
+    path: WikiPagePath,
+    form: Form<RestoreForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    restore_inner(path, form, w)
+}
+
+#[derive(FromForm)]
+struct RenameForm<'r> {
+    new_path: &'r str,
+    message: &'r str,
+    authenticity_token: &'r str,
+}
+
+fn rename_save_inner(
+    path: WikiPagePath,
+    form: Form<RenameForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    let new_path = WikiPagePath::new(
+        form.new_path
+            .split('/')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect(),
+    );
+    let message = if form.message.trim().is_empty() {
+        Cow::Owned(format!(
+            "Rename {} to {}",
+            path.segments.join("/"),
+            new_path.segments.join("/")
+        ))
+    } else {
+        Cow::Borrowed(form.message.trim())
+    };
+    w.rename_file(&path.segments, &new_path.segments, &message)?;
+    Ok(response::Redirect::to(uri!(page(new_path))))
+}
+
+#[post("/rename/<path..>", data = "<form>")]
+fn rename_save(
+    path: WikiPagePath,
+    form: Form<RenameForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    rename_save_inner(path, form, w)
+}
+
+fn rename_view_inner(
     path: WikiPagePath,
     w: &State<Wiki>,
-    as_create: bool,
 ) -> Result<(ContentType, String), MyError> {
-    let content = w.read_file(&path.segments).unwrap_or_else(|_| vec![]);
-    let content = std::str::from_utf8(&content)?;
-    let post_url = if as_create {
-        uri!(new_save(&path))
+    let title = format!("Renaming {}", path.file_name().ok_or(MyError::InvalidPath)?);
+    let post_url = uri!(rename_save(&path)).to_string();
+    let view_url = uri!(page(&path)).to_string();
+    let html = render_rename_page(
+        &title,
+        &post_url,
+        &view_url,
+        &path.segments.join("/"),
+        message_placeholder(w),
+        path.page_breadcrumbs(),
+        &CSRF_TOKEN,
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[get("/rename/<path..>")]
+fn rename_view(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    rename_view_inner(path, w)
+}
+
+#[derive(FromForm)]
+struct DeleteForm<'r> {
+    message: &'r str,
+    authenticity_token: &'r str,
+}
+
+fn delete_save_inner(
+    path: WikiPagePath,
+    form: Form<DeleteForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    let message = if form.message.trim().is_empty() {
+        Cow::Owned(format!("Delete {}", path.segments.join("/")))
     } else {
-        uri!(edit_save(&path))
+        Cow::Borrowed(form.message.trim())
     };
-    let view_url = uri!(page(&path));
-    let preview_url = uri!(preview(&path));
-    let title = format!("Editing {}", path.file_name().expect("Ill-formed path"));
-    let message_placeholder = if w
+    w.delete_file(&path.segments, &message)?;
+    let mut directory = path.directory().ok_or(MyError::InvalidPath)?;
+    // Git does not track empty directories, so the directory may be gone now too.
+    if !w.directory_exists(&directory.segments)? {
+        directory = WikiPagePath::new(vec![]);
+    }
+    Ok(response::Redirect::to(uri!(overview(directory))))
+}
+
+#[post("/delete/<path..>", data = "<form>")]
+fn delete_save(
+    path: WikiPagePath,
+    form: Form<DeleteForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    delete_save_inner(path, form, w)
+}
+
+fn delete_view_inner(
+    path: WikiPagePath,
+    w: &State<Wiki>,
+) -> Result<(ContentType, String), MyError> {
+    let title = format!("Deleting {}", path.file_name().ok_or(MyError::InvalidPath)?);
+    let post_url = uri!(delete_save(&path)).to_string();
+    let view_url = uri!(page(&path)).to_string();
+    let html = render_delete_page(
+        &title,
+        &post_url,
+        &view_url,
+        &path.segments.join("/"),
+        message_placeholder(w),
+        path.page_breadcrumbs(),
+        &CSRF_TOKEN,
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[get("/delete/<path..>")]
+fn delete_view(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    delete_view_inner(path, w)
+}
+
+fn default_edit_message(path: &WikiPagePath) -> String {
+    format!("Update {}", path.segments.join("/"))
+}
+
+fn default_new_message(path: &WikiPagePath) -> String {
+    format!("Create {}", path.segments.join("/"))
+}
+
+fn branch_switcher(w: &State<Wiki>) -> Option<BranchSwitcher> {
+    if !w
         .repo_capabilities()
+        .contains(RepositoryCapability::SUPPORTS_BRANCHES)
+    {
+        return None;
+    }
+    let branches = w.branches().ok()?;
+    Some(BranchSwitcher::new(
+        w.current_branch(),
+        branches,
+        uri!(switch_branch).to_string(),
+        &CSRF_TOKEN,
+    ))
+}
+
+#[derive(FromForm)]
+struct BranchForm<'r> {
+    branch: &'r str,
+    authenticity_token: &'r str,
+}
+
+#[post("/branch", data = "<form>")]
+fn switch_branch(
+    form: Form<BranchForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    w.switch_branch(form.branch)?;
+    Ok(response::Redirect::to(uri!(index)))
+}
+
+#[get("/sync")]
+fn sync_status(w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    let last_sync = w.last_sync().as_ref().map(LastSync::new);
+    let html = render_sync_status(
+        w.settings().sync(),
+        w.current_branch(),
+        last_sync,
+        &uri!(sync_now).to_string(),
+        &CSRF_TOKEN,
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[derive(FromForm)]
+struct SyncForm<'r> {
+    authenticity_token: &'r str,
+}
+
+#[post("/sync", data = "<form>")]
+async fn sync_now(
+    form: Form<SyncForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<response::Redirect, MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    // Fetching and pushing block, so keep them off the async executor.
+    let w = w.inner().clone();
+    let result = rocket::tokio::task::spawn_blocking(move || w.sync())
+        .await
+        .map_err(std::io::Error::from)?;
+    match result {
+        Err(err @ MyError::SyncNotConfigured) => Err(err),
+        // Otherwise the outcome is shown on the status page, whether or not it worked.
+        _ => Ok(response::Redirect::to(uri!(sync_status))),
+    }
+}
+
+fn message_placeholder(w: &State<Wiki>) -> Option<String> {
+    if w.repo_capabilities()
         .contains(RepositoryCapability::SUPPORTS_EDIT_MESSAGE)
     {
         Some("Explain this change. (Optional)".to_string())
     } else {
         None
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_editor(
+    path: &WikiPagePath,
+    w: &State<Wiki>,
+    as_create: bool,
+    message: &str,
+    author_name: &str,
+    author_email: &str,
+    content: &str,
+    base_revision: Option<&str>,
+    conflict_hunks: Option<Vec<DiffHunkView>>,
+) -> Result<(ContentType, String), MyError> {
+    let post_url = if as_create {
+        uri!(new_save(path))
+    } else {
+        uri!(edit_save(path))
     };
+    let view_url = uri!(page(path));
+    let preview_url = uri!(preview(path));
+    let upload_url = uri!(upload(WikiPagePath::from_slice(path.directories())));
+    let title = format!("Editing {}", path.file_name().expect("Ill-formed path"));
     let html = render_edit_page(
         &title,
         &post_url.to_string(),
         &view_url.to_string(),
         &preview_url.to_string(),
-        message_placeholder,
+        &upload_url.to_string(),
+        message_placeholder(w),
+        message,
+        author_name,
+        author_email,
         content,
+        base_revision,
+        conflict_hunks,
         path.page_breadcrumbs(),
         &CSRF_TOKEN,
+        branch_switcher(w),
     )?;
     Ok((ContentType::HTML, html))
 }
 
+fn edit_view_inner(
+    path: WikiPagePath,
+    w: &State<Wiki>,
+    as_create: bool,
+) -> Result<(ContentType, String), MyError> {
+    // The content and the revision it is based on must match, for merging to work.
+    let snapshot = w.snapshot()?;
+    let content = snapshot
+        .read_raw_file(&path.segments)
+        .unwrap_or_else(|_| vec![]);
+    let content = std::str::from_utf8(&content)?;
+    render_editor(
+        &path,
+        w,
+        as_create,
+        "",
+        "",
+        "",
+        content,
+        snapshot.commit_id(),
+        None,
+    )
+}
+
 #[get("/edit/<path..>")]
 fn edit_view(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
     edit_view_inner(path, w, false)
@@ -282,43 +627,91 @@ fn new_view(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String)
 fn page_response(
     page: crate::page::Page,
     path: &WikiPagePath,
+    revision: Option<&CommitInfo>,
+    w: &State<Wiki>,
 ) -> Result<(ContentType, String), MyError> {
     let edit_url = uri!(edit_view(path)).to_string();
+    let rename_url = uri!(rename_view(path)).to_string();
+    let delete_url = uri!(delete_view(path)).to_string();
+    let (history_url, blame_url) = if w
+        .repo_capabilities()
+        .contains(RepositoryCapability::SUPPORTS_HISTORY)
+    {
+        (
+            Some(uri!(history(path)).to_string()),
+            Some(uri!(blame(path)).to_string()),
+        )
+    } else {
+        (None, None)
+    };
+    let current_url = uri!(page(path)).to_string();
+    let restore_url = uri!(restore(path)).to_string();
+    let revision =
+        revision.map(|commit| RevisionNotice::new(commit, &current_url, &restore_url, &CSRF_TOKEN));
     let overview_url = uri!(overview(path.directory().unwrap())).to_string();
+    let backlinks = w.backlinks(&path.segments)?;
+    let backlinks_url = uri!(backlinks(path)).to_string();
     let html = render_page(
         &page.title,
         &edit_url,
+        &rename_url,
+        &delete_url,
+        history_url.as_deref(),
+        blame_url.as_deref(),
+        revision,
         &overview_url,
         &page.body,
+        &backlinks,
+        &backlinks_url,
         path.page_breadcrumbs(),
+        branch_switcher(w),
     )?;
     Ok((ContentType::HTML, html))
 }
 
-fn page_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<WikiPageResponder, MyError> {
-    match w.read_file(&path.segments) {
-        Ok(bytes) => {
-            let file_info = path.file_stem_and_extension();
-            Ok(match file_info {
-                Some((file_stem, file_ext)) => {
-                    match crate::page::get_page(file_stem, file_ext, &bytes, w.settings())? {
-                        Some(page_model) => {
-                            WikiPageResponder::Page(page_response(page_model, &path)?)
-                        }
-                        None => match ContentType::from_extension(file_ext) {
-                            Some(mine_type) => WikiPageResponder::TypedFile((mine_type, bytes)),
-                            None => WikiPageResponder::File(bytes),
-                        },
-                    }
+/// Responds with a file read from `snapshot`, rendering it if it is a page.
+fn file_response(
+    path: &WikiPagePath,
+    bytes: Vec<u8>,
+    snapshot: &Snapshot,
+    revision: Option<&CommitInfo>,
+    w: &State<Wiki>,
+) -> Result<WikiPageResponder, MyError> {
+    let file_info = path.file_stem_and_extension();
+    Ok(match file_info {
+        Some((file_stem, file_ext)) => {
+            let base_dir = path.directory().ok_or(MyError::InvalidPath)?;
+            let resolve = |link: &PageLink| snapshot.resolve_link(&base_dir.segments, link);
+            match crate::page::get_page(file_stem, file_ext, &bytes, w.settings(), &resolve)? {
+                Some(page_model) => {
+                    WikiPageResponder::Page(page_response(page_model, path, revision, w)?)
                 }
-                None => WikiPageResponder::File(bytes),
-            })
+                None => match ContentType::from_extension(file_ext) {
+                    Some(mine_type) => WikiPageResponder::TypedFile((mine_type, bytes)),
+                    None => WikiPageResponder::File(bytes),
+                },
+            }
         }
+        None => WikiPageResponder::File(bytes),
+    })
+}
+
+fn page_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<WikiPageResponder, MyError> {
+    let snapshot = w.snapshot()?;
+    if snapshot.file_exists(&path.segments).unwrap_or(false) {
+        if let Some(page) = w.render_page(&snapshot, &path.segments)? {
+            return Ok(WikiPageResponder::Page(page_response(
+                page, &path, None, w,
+            )?));
+        }
+    }
+    match snapshot.read_file(&path.segments) {
+        Ok(bytes) => file_response(&path, bytes, &snapshot, None, w),
         Err(_) => {
-            if w.directory_exists(&path.segments).unwrap() {
+            if snapshot.directory_exists(&path.segments).unwrap() {
                 let file_name = format!("{}.md", w.settings().index_page());
                 let file_path = path.append_segment(&file_name);
-                if w.file_exists(&file_path.segments)? {
+                if snapshot.file_exists(&file_path.segments)? {
                     Ok(WikiPageResponder::Redirect(response::Redirect::to(uri!(
                         page(file_path)
                     ))))
@@ -341,6 +734,7 @@ fn page_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<WikiPageResponder,
                                     &create_url.to_string(),
                                     &overview_url,
                                     path.page_breadcrumbs(),
+                                    branch_switcher(w),
                                 )
                                 .unwrap(),
                             )),
@@ -360,8 +754,189 @@ fn page(path: WikiPagePath, w: &State<Wiki>) -> Result<WikiPageResponder, MyErro
     page_inner(path, w)
 }
 
+fn page_at_revision_inner(
+    path: WikiPagePath,
+    rev: &str,
+    w: &State<Wiki>,
+) -> Result<WikiPageResponder, MyError> {
+    let commit = match w.get_commit(rev) {
+        Ok(commit) => commit,
+        Err(MyError::RevisionNotFound { revision }) => {
+            return Ok(WikiPageResponder::NotFound(response::status::NotFound(
+                format!("Revision not found: {}", revision),
+            )));
+        }
+        Err(err) => return Err(err),
+    };
+    // Read using the full commit ID, in case a branch moves while we are reading. Links are
+    // resolved against the same commit, so they go where they went at the time.
+    let snapshot = w.snapshot_at(&commit.id)?;
+    match snapshot.read_file(&path.segments) {
+        Ok(bytes) => file_response(&path, bytes, &snapshot, Some(&commit), w),
+        Err(_) => Ok(WikiPageResponder::NotFound(response::status::NotFound(
+            format!("File not found in revision {}: {}", commit.short_id, path),
+        ))),
+    }
+}
+
+#[get("/page/<path..>?<rev>")]
+fn page_at_revision(
+    path: WikiPagePath,
+    rev: &str,
+    w: &State<Wiki>,
+) -> Result<WikiPageResponder, MyError> {
+    page_at_revision_inner(path, rev, w)
+}
+
+fn history_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    let commits = w.history(&path.segments)?;
+    let entries = commits
+        .iter()
+        .enumerate()
+        .map(|(ndx, commit)| {
+            let revision_url = uri!(page_at_revision(&path, &commit.id)).to_string();
+            // Commits are newest first, so the next one is the previous change to this file.
+            let compare_url = commits
+                .get(ndx + 1)
+                .map(|prev| uri!(compare(&path, &prev.id, &commit.id)).to_string());
+            HistoryEntry::new(commit, revision_url, compare_url)
+        })
+        .collect();
+    let title = format!(
+        "History of {}",
+        path.file_name().ok_or(MyError::InvalidPath)?
+    );
+    let view_url = uri!(page(&path)).to_string();
+    let blame_url = uri!(blame(&path)).to_string();
+    let overview_url = uri!(overview(path.directory().unwrap())).to_string();
+    let html = render_history(
+        &title,
+        &view_url,
+        &blame_url,
+        &overview_url,
+        entries,
+        path.page_breadcrumbs(),
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[get("/history/<path..>")]
+fn history(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    history_inner(path, w)
+}
+
+fn backlinks_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    let backlinks = w.backlinks(&path.segments)?;
+    let title = format!(
+        "Pages linking to {}",
+        path.file_name().ok_or(MyError::InvalidPath)?
+    );
+    let view_url = uri!(page(&path)).to_string();
+    let overview_url = uri!(overview(path.directory().unwrap())).to_string();
+    let html = render_backlinks(
+        &title,
+        &view_url,
+        &overview_url,
+        &backlinks,
+        w.indexing_progress(),
+        path.page_breadcrumbs(),
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[get("/backlinks/<path..>")]
+fn backlinks(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    backlinks_inner(path, w)
+}
+
+fn blame_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    let hunks = w.blame(&path.segments)?;
+    let hunks = hunks
+        .iter()
+        .map(|hunk| {
+            let revision_url = uri!(page_at_revision(&path, &hunk.commit.id)).to_string();
+            BlameHunkView::new(hunk, revision_url)
+        })
+        .collect();
+    let title = format!(
+        "Blame for {}",
+        path.file_name().ok_or(MyError::InvalidPath)?
+    );
+    let view_url = uri!(page(&path)).to_string();
+    let history_url = uri!(history(&path)).to_string();
+    let overview_url = uri!(overview(path.directory().unwrap())).to_string();
+    let html = render_blame(
+        &title,
+        &view_url,
+        &history_url,
+        &overview_url,
+        hunks,
+        path.page_breadcrumbs(),
+        branch_switcher(w),
+    )?;
+    Ok((ContentType::HTML, html))
+}
+
+#[get("/blame/<path..>")]
+fn blame(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
+    blame_inner(path, w)
+}
+
+fn compare_inner(
+    path: WikiPagePath,
+    from: &str,
+    to: &str,
+    w: &State<Wiki>,
+) -> Result<WikiPageResponder, MyError> {
+    let (from, to) = match (w.get_commit(from), w.get_commit(to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(MyError::RevisionNotFound { revision }), _)
+        | (_, Err(MyError::RevisionNotFound { revision })) => {
+            return Ok(WikiPageResponder::NotFound(response::status::NotFound(
+                format!("Revision not found: {}", revision),
+            )));
+        }
+        (Err(err), _) | (_, Err(err)) => return Err(err),
+    };
+    let hunks = w.diff_file(&path.segments, &from.id, &to.id)?;
+    let hunks = hunks.iter().map(DiffHunkView::new).collect();
+    let title = format!(
+        "Changes to {}",
+        path.file_name().ok_or(MyError::InvalidPath)?
+    );
+    let view_url = uri!(page(&path)).to_string();
+    let history_url = uri!(history(&path)).to_string();
+    let overview_url = uri!(overview(path.directory().unwrap())).to_string();
+    let from_url = uri!(page_at_revision(&path, &from.id)).to_string();
+    let to_url = uri!(page_at_revision(&path, &to.id)).to_string();
+    let html = render_compare(
+        &title,
+        &view_url,
+        &history_url,
+        &overview_url,
+        CompareRevision::new(&from, from_url),
+        CompareRevision::new(&to, to_url),
+        hunks,
+        path.page_breadcrumbs(),
+        branch_switcher(w),
+    )?;
+    Ok(WikiPageResponder::Page((ContentType::HTML, html)))
+}
+
+#[get("/compare/<path..>?<from>&<to>")]
+fn compare(
+    path: WikiPagePath,
+    from: &str,
+    to: &str,
+    w: &State<Wiki>,
+) -> Result<WikiPageResponder, MyError> {
+    compare_inner(path, from, to, w)
+}
+
 fn overview_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, String), MyError> {
-    let mut entries = w.enumerate_files(&path.segments)?;
+    let mut entries = w.snapshot()?.enumerate_files(&path.segments)?;
     entries.sort();
     let entries = entries;
 
@@ -387,7 +962,13 @@ fn overview_inner(path: WikiPagePath, w: &State<Wiki>) -> Result<(ContentType, S
         })
         .collect();
 
-    let html = render_overview("Overview", path.overview_breadcrumbs(), directories, files)?;
+    let html = render_overview(
+        "Overview",
+        path.overview_breadcrumbs(),
+        directories,
+        files,
+        branch_switcher(w),
+    )?;
     Ok((ContentType::HTML, html))
 }
 
@@ -411,7 +992,14 @@ fn search_inner(
         }
     });
     let next_url = Some(uri!(search(q, Some(offset.unwrap_or(0) + RESULTS_PER_PAGE))).to_string());
-    let html = render_search_results(q, results, prev_url, next_url)?;
+    let html = render_search_results(
+        q,
+        results,
+        prev_url,
+        next_url,
+        w.indexing_progress(),
+        branch_switcher(w),
+    )?;
     Ok((ContentType::HTML, html))
 }
 
@@ -430,7 +1018,16 @@ fn preview_inner(
     w: &State<Wiki>,
 ) -> Result<(ContentType, String), MyError> {
     let (file_stem, file_extension) = path.file_stem_and_extension().unwrap();
-    let page = crate::page::get_page(file_stem, file_extension, content.as_bytes(), w.settings())?;
+    let snapshot = w.snapshot()?;
+    let base_dir = path.directory().ok_or(MyError::InvalidPath)?;
+    let resolve = |link: &PageLink| snapshot.resolve_link(&base_dir.segments, link);
+    let page = crate::page::get_page(
+        file_stem,
+        file_extension,
+        content.as_bytes(),
+        w.settings(),
+        &resolve,
+    )?;
     let page = page.unwrap();
     Ok((ContentType::HTML, page.body))
 }
@@ -445,6 +1042,69 @@ fn preview(
     preview_inner(path, content, w)
 }
 
+#[derive(FromForm)]
+struct UploadForm<'r> {
+    file: TempFile<'r>,
+    message: &'r str,
+    authenticity_token: &'r str,
+}
+
+/// File extensions that are linked to as images rather than as plain links.
+const IMAGE_EXTENSIONS: &[&str] = &["apng", "avif", "gif", "jpeg", "jpg", "png", "svg", "webp"];
+
+/// The Markdown that links to a file uploaded next to a page.
+fn upload_link(file_name: &str) -> String {
+    let target = relative_link::<&str>(&[], &[file_name]);
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => {
+            format!("![{}]({})", stem, target)
+        }
+        _ => format!("[{}]({})", file_name, target),
+    }
+}
+
+/// Adds a file to a directory. Responds with the Markdown for linking to it from a page in the
+/// same directory, which the editor inserts.
+#[post("/upload/<dir..>", data = "<form>")]
+async fn upload(
+    dir: WikiPagePath<'_>,
+    form: Form<UploadForm<'_>>,
+    w: &State<Wiki>,
+) -> Result<(ContentType, String), MyError> {
+    check_csrf_token(form.authenticity_token)?;
+    // Browsers send only the name, but some used to send the whole path on the client.
+    let file_name = form
+        .file
+        .raw_name()
+        .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str())
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .filter(|name| !name.is_empty())
+        .ok_or(MyError::InvalidPath)?
+        .to_owned();
+    let mut path: Vec<String> = dir.segments.iter().map(|s| s.to_string()).collect();
+    path.push(file_name.clone());
+    let message = if form.message.trim().is_empty() {
+        format!("Upload {}", path.join("/"))
+    } else {
+        form.message.trim().to_owned()
+    };
+
+    let mut content = Vec::new();
+    form.file.open().await?.read_to_end(&mut content).await?;
+    // Committing and reindexing block, so keep them off the async executor.
+    let w = w.inner().clone();
+    rocket::tokio::task::spawn_blocking(move || {
+        let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+        if w.file_exists(&path)? || w.directory_exists(&path)? {
+            return Err(MyError::PathAlreadyExists);
+        }
+        w.write_file(&path, &message, &content, None)
+    })
+    .await
+    .map_err(std::io::Error::from)??;
+    Ok((ContentType::Plain, upload_link(&file_name)))
+}
+
 #[get("/")]
 fn index(w: &State<Wiki>) -> response::Redirect {
     let file_name = format!("{}.md", w.settings().index_page());
@@ -455,7 +1115,31 @@ fn index(w: &State<Wiki>) -> response::Redirect {
 pub fn mount_routes(rocket: Rocket<Build>) -> Rocket<Build> {
     rocket.mount(
         "/",
-        routes![page, search, edit_save, new_save, edit_view, new_view, preview, overview, index],
+        routes![
+            page,
+            page_at_revision,
+            search,
+            edit_save,
+            new_save,
+            restore,
+            rename_view,
+            rename_save,
+            delete_view,
+            delete_save,
+            edit_view,
+            new_view,
+            preview,
+            overview,
+            history,
+            backlinks,
+            compare,
+            blame,
+            switch_branch,
+            upload,
+            sync_status,
+            sync_now,
+            index
+        ],
     )
 }
 
@@ -523,4 +1207,105 @@ mod tests {
         let file = folder.append_segment("file");
         assert_eq!(file.segments, vec!["folder", "file"]);
     }
+
+    #[test]
+    fn test_upload_link() {
+        assert_eq!("![diagram](diagram.PNG)", upload_link("diagram.PNG"));
+        assert_eq!(
+            "[My Report.pdf](My%20Report.pdf)",
+            upload_link("My Report.pdf")
+        );
+        assert_eq!("[notes](notes)", upload_link("notes"));
+        assert_eq!("![a (1)](a%20%281%29.png)", upload_link("a (1).png"));
+        assert_eq!(
+            "[50% #1?.txt](50%25%20%231%3F.txt)",
+            upload_link("50% #1?.txt")
+        );
+    }
+
+    /// Posts an edit of `page.md` that started from `base_revision`.
+    fn post_edit<'c>(
+        client: &'c rocket::local::blocking::Client,
+        content: &str,
+        base_revision: &str,
+    ) -> rocket::local::blocking::LocalResponse<'c> {
+        let body = format!(
+            "content={}&message=&base_revision={}&author_name=Test&author_email=test%40example.com&authenticity_token={}",
+            content.replace('\n', "%0A"),
+            base_revision,
+            *CSRF_TOKEN
+        );
+        client
+            .post("/edit/page.md")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+    }
+
+    fn test_client(wiki: Wiki) -> rocket::local::blocking::Client {
+        rocket::local::blocking::Client::tracked(mount_routes(rocket::build().manage(wiki)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_edit_save() {
+        let (_wiki_dir, wiki) = crate::wiki::tests::test_wiki(&[("page.md", "one\n")]);
+        let base = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        let client = test_client(wiki);
+
+        let response = post_edit(&client, "two\n", &base);
+        assert_eq!(rocket::http::Status::SeeOther, response.status());
+        assert_eq!(
+            Some("/page/page.md"),
+            response.headers().get_one("Location")
+        );
+    }
+
+    #[test]
+    fn test_page_at_revision() {
+        let (wiki_dir, wiki) = crate::wiki::tests::test_wiki(&[("page.md", "first version\n")]);
+        let first = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        crate::wiki::tests::commit_files(wiki_dir.path(), &[("page.md", "second version\n")]);
+        let client = test_client(wiki);
+
+        let response = client
+            .get(format!("/page/page.md?rev={}", first))
+            .dispatch();
+        assert_eq!(rocket::http::Status::Ok, response.status());
+        let html = response.into_string().unwrap();
+        assert!(html.contains("first version"));
+        assert!(!html.contains("second version"));
+
+        let response = client.get("/page/page.md?rev=missing").dispatch();
+        assert_eq!(rocket::http::Status::NotFound, response.status());
+    }
+
+    #[test]
+    fn test_compare_unknown_revision() {
+        let (_wiki_dir, wiki) = crate::wiki::tests::test_wiki(&[("page.md", "one\n")]);
+        let head = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        let client = test_client(wiki);
+
+        for query in [
+            format!("from={}&to=missing", head),
+            format!("from=..bad&to={}", head),
+        ] {
+            let response = client.get(format!("/compare/page.md?{}", query)).dispatch();
+            assert_eq!(rocket::http::Status::NotFound, response.status());
+        }
+    }
+
+    #[test]
+    fn test_edit_save_conflict_shows_editor() {
+        let (wiki_dir, wiki) = crate::wiki::tests::test_wiki(&[("page.md", "one\n")]);
+        let base = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        let head = crate::wiki::tests::commit_files(wiki_dir.path(), &[("page.md", "theirs\n")]);
+        let client = test_client(wiki);
+
+        let response = post_edit(&client, "mine\n", &base);
+        assert_eq!(rocket::http::Status::Conflict, response.status());
+        let html = response.into_string().unwrap();
+        assert!(html.contains("&#60;&#60;&#60;&#60;&#60;&#60;&#60; your changes"));
+        assert!(html.contains(&format!("value=\"{}\"", head)));
+    }
 }
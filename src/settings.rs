@@ -1,11 +1,12 @@
 use std::default::Default;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::Deserialize;
 
 use crate::error::MyError;
-use crate::repository::{RepoBox, RepositoryItem};
+use crate::repository::{Author, RepoBox, RepositoryItem};
 
 use shadow_rs::shadow;
 
@@ -25,16 +26,39 @@ pub struct Args {
     /// Use the file system to read the wiki, not Git.
     #[arg(long)]
     fs: bool,
+    /// The Git branch to serve and commit to. Defaults to the branch that is checked out.
+    #[arg(long = "ref", value_name = "BRANCH")]
+    reference: Option<String>,
+    /// The name to commit changes as. Defaults to user.name from the Git config.
+    #[arg(long)]
+    author_name: Option<String>,
+    /// The email address to commit changes as. Defaults to user.email from the Git config.
+    #[arg(long)]
+    author_email: Option<String>,
+    /// Where to keep the search index between runs. Defaults to a directory in the user's cache
+    /// directory.
+    #[arg(long, value_name = "DIR")]
+    index_dir: Option<PathBuf>,
+    /// Keep the search index in memory and rebuild it every time the wiki starts.
+    #[arg(long, conflicts_with = "index_dir")]
+    no_index_dir: bool,
 }
 
 impl Args {
-    pub fn git_repo(&self) -> Option<PathBuf> {
-        self.git_repo.clone()
+    /// The wiki directory, which is the current directory if none was given.
+    pub fn git_repo(&self) -> PathBuf {
+        self.git_repo
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().unwrap())
     }
 
     pub fn use_fs(&self) -> bool {
         self.fs
     }
+
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
 }
 
 #[derive(Default, Deserialize)]
@@ -45,6 +69,48 @@ struct Config {
     /// Whether the first H1 should become the title of a page.
     #[serde(rename = "h1-title")]
     h1_title: Option<bool>,
+    /// The Git branch to serve and commit to.
+    branch: Option<String>,
+    /// The name to commit changes as.
+    #[serde(rename = "author-name")]
+    author_name: Option<String>,
+    /// The email address to commit changes as.
+    #[serde(rename = "author-email")]
+    author_email: Option<String>,
+    /// Synchronizing with a Git remote.
+    sync: Option<SyncConfig>,
+}
+
+#[derive(Deserialize)]
+struct SyncConfig {
+    /// The name of the remote to pull from and push to. "origin" by default.
+    remote: Option<String>,
+    /// How often to sync, in seconds. Only syncs when asked to if not set.
+    interval: Option<u64>,
+    /// Whether to sync after every change made through the wiki.
+    #[serde(rename = "push-on-commit")]
+    push_on_commit: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncSettings {
+    remote: String,
+    interval: Option<Duration>,
+    push_on_commit: bool,
+}
+
+impl SyncSettings {
+    pub fn remote(&self) -> &str {
+        &self.remote
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    pub fn push_on_commit(&self) -> bool {
+        self.push_on_commit
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -53,6 +119,10 @@ pub struct Settings {
     h1_title: bool,
     host: IpAddr,
     port: u16,
+    branch: Option<String>,
+    author: Option<Author>,
+    sync: Option<SyncSettings>,
+    index_dir: Option<PathBuf>,
 }
 
 impl Settings {
@@ -63,9 +133,19 @@ impl Settings {
             h1_title,
             host: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 8000,
+            branch: None,
+            author: None,
+            sync: None,
+            index_dir: None,
         }
     }
 
+    #[cfg(test)]
+    pub(crate) fn with_index_dir(mut self, index_dir: &Path) -> Settings {
+        self.index_dir = Some(index_dir.to_owned());
+        self
+    }
+
     pub fn index_page(&self) -> &str {
         &self.index_page
     }
@@ -81,6 +161,44 @@ impl Settings {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// The default author of commits, if one was configured for smeagol rather than for Git.
+    pub fn author(&self) -> Option<&Author> {
+        self.author.as_ref()
+    }
+
+    pub fn sync(&self) -> Option<&SyncSettings> {
+        self.sync.as_ref()
+    }
+
+    /// The directory the search index is stored in, or `None` to keep it in memory.
+    pub fn index_dir(&self) -> Option<&Path> {
+        self.index_dir.as_deref()
+    }
+}
+
+/// The directory for the search index of the wiki in `wiki_dir`, inside the user's cache
+/// directory. Each wiki gets its own directory, named after the path of the wiki.
+fn default_index_dir(wiki_dir: &Path) -> Option<PathBuf> {
+    let cache_dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("LOCALAPPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => dir,
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        }
+    };
+    let wiki_dir = wiki_dir.canonicalize().ok()?;
+    let name: String = wiki_dir
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    Some(cache_dir.join("smeagol").join("index").join(name))
 }
 
 fn load_config(repo: &RepoBox) -> Result<Config, MyError> {
@@ -108,6 +226,13 @@ fn load_config(repo: &RepoBox) -> Result<Config, MyError> {
 
 pub fn parse_settings_from_args(args: Args, repo: &RepoBox) -> Result<Settings, MyError> {
     let config = load_config(repo)?;
+    let index_dir = if args.no_index_dir {
+        None
+    } else {
+        args.index_dir
+            .clone()
+            .or_else(|| default_index_dir(&args.git_repo()))
+    };
 
     let ret = Settings {
         index_page: config.index_page.unwrap_or_else(|| "README".into()),
@@ -116,6 +241,20 @@ pub fn parse_settings_from_args(args: Args, repo: &RepoBox) -> Result<Settings,
             .host
             .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
         port: args.port.unwrap_or(8000),
+        branch: args.reference.or(config.branch),
+        author: Author::from_parts(
+            args.author_name.or(config.author_name).as_deref(),
+            args.author_email.or(config.author_email).as_deref(),
+        )?,
+        sync: config.sync.map(|sync| SyncSettings {
+            remote: sync.remote.unwrap_or_else(|| "origin".into()),
+            interval: sync
+                .interval
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            push_on_commit: sync.push_on_commit.unwrap_or(false),
+        }),
+        index_dir,
     };
     Ok(ret)
 }
@@ -1,10 +1,22 @@
 use std::ops::Deref;
 
 use askama::Template;
+use time::macros::format_description;
+use time::OffsetDateTime;
 
 use crate::assets::favicon_png_uri;
 use crate::assets::primer_css_uri;
+use crate::diff::highlight_words;
+use crate::diff::DiffRow;
+use crate::repository::BlameHunk;
+use crate::repository::CommitInfo;
+use crate::repository::DiffHunk;
+use crate::repository::PullResult;
+use crate::settings::SyncSettings;
+use crate::wiki::Backlink;
+use crate::wiki::IndexingProgress;
 use crate::wiki::SearchResult;
+use crate::wiki::SyncStatus;
 
 use shadow_rs::shadow;
 
@@ -13,6 +25,11 @@ shadow!(build);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const SHORT_COMMIT: &str = build::SHORT_COMMIT;
 
+fn format_date(time: &OffsetDateTime) -> String {
+    time.format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+        .unwrap_or_default()
+}
+
 pub struct Breadcrumb<'a> {
     name: &'a str,
     href: String,
@@ -35,10 +52,44 @@ struct LayoutTemplate<'a> {
     overview_url: String,
     version: &'static str,
     short_sha: &'static str,
+    branch_switcher: Option<BranchSwitcher>,
+}
+
+/// Lists the local branches in the header, so that the wiki can be switched to another one.
+pub struct BranchSwitcher {
+    current: Option<String>,
+    branches: Vec<String>,
+    post_url: String,
+    authenticity_token: String,
+}
+
+impl BranchSwitcher {
+    pub fn new(
+        current: Option<String>,
+        branches: Vec<String>,
+        post_url: String,
+        authenticity_token: &str,
+    ) -> Self {
+        BranchSwitcher {
+            current,
+            branches,
+            post_url,
+            authenticity_token: authenticity_token.to_owned(),
+        }
+    }
+
+    fn is_current(&self, branch: &str) -> bool {
+        self.current.as_deref() == Some(branch)
+    }
 }
 
 impl<'a> LayoutTemplate<'a> {
-    fn new(title: &'a str, overview_url: &'a str, breadcrumbs: Vec<Breadcrumb<'a>>) -> Self {
+    fn new(
+        title: &'a str,
+        overview_url: &'a str,
+        breadcrumbs: Vec<Breadcrumb<'a>>,
+        branch_switcher: Option<BranchSwitcher>,
+    ) -> Self {
         let primer_css_uri = primer_css_uri();
         let favicon_png_uri = favicon_png_uri();
         Self {
@@ -49,6 +100,7 @@ impl<'a> LayoutTemplate<'a> {
             title: title.to_owned(),
             version: VERSION,
             short_sha: SHORT_COMMIT,
+            branch_switcher,
         }
     }
 }
@@ -58,7 +110,43 @@ impl<'a> LayoutTemplate<'a> {
 struct ViewPageTemplate<'a> {
     layout: &'a LayoutTemplate<'a>,
     edit_url: &'a str,
+    rename_url: &'a str,
+    delete_url: &'a str,
+    history_url: Option<&'a str>,
+    blame_url: Option<&'a str>,
+    revision: Option<RevisionNotice<'a>>,
     content: &'a str,
+    /// The first few pages linking to this one, out of `backlinks_total`.
+    backlinks: &'a [Backlink],
+    backlinks_total: usize,
+    backlinks_url: &'a str,
+}
+
+/// Shown above a page when viewing it as it was in an older commit.
+pub struct RevisionNotice<'a> {
+    commit: &'a CommitInfo,
+    date: String,
+    current_url: &'a str,
+    restore_url: &'a str,
+    authenticity_token: &'a str,
+}
+
+impl<'a> RevisionNotice<'a> {
+    pub fn new(
+        commit: &'a CommitInfo,
+        current_url: &'a str,
+        restore_url: &'a str,
+        authenticity_token: &'a str,
+    ) -> Self {
+        let date = format_date(&commit.time);
+        RevisionNotice {
+            commit,
+            date,
+            current_url,
+            restore_url,
+            authenticity_token,
+        }
+    }
 }
 
 impl<'a> Deref for ViewPageTemplate<'a> {
@@ -69,22 +157,375 @@ impl<'a> Deref for ViewPageTemplate<'a> {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_page(
     title: &str,
     edit_url: &str,
+    rename_url: &str,
+    delete_url: &str,
+    history_url: Option<&str>,
+    blame_url: Option<&str>,
+    revision: Option<RevisionNotice<'_>>,
     overview_url: &str,
     content: &str,
+    backlinks: &[Backlink],
+    backlinks_url: &str,
     breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
 ) -> askama::Result<String> {
-    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs);
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
     let page = ViewPageTemplate {
         layout: &layout,
         edit_url,
+        rename_url,
+        delete_url,
+        history_url,
+        blame_url,
+        revision,
         content,
+        backlinks: &backlinks[..backlinks.len().min(BACKLINKS_ON_PAGE)],
+        backlinks_total: backlinks.len(),
+        backlinks_url,
     };
     page.render()
 }
 
+/// How many of the pages linking to a page are listed below it.
+const BACKLINKS_ON_PAGE: usize = 10;
+
+#[derive(Template)]
+#[template(path = "backlinks.html")]
+struct BacklinksTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    view_url: &'a str,
+    backlinks: &'a [Backlink],
+    indexing: Option<IndexingProgress>,
+}
+
+impl<'a> Deref for BacklinksTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+pub fn render_backlinks(
+    title: &str,
+    view_url: &str,
+    overview_url: &str,
+    backlinks: &[Backlink],
+    indexing: Option<IndexingProgress>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
+    let template = BacklinksTemplate {
+        layout: &layout,
+        view_url,
+        backlinks,
+        indexing,
+    };
+    template.render()
+}
+
+pub struct HistoryEntry<'a> {
+    commit: &'a CommitInfo,
+    date: String,
+    revision_url: String,
+    compare_url: Option<String>,
+}
+
+impl<'a> HistoryEntry<'a> {
+    pub fn new(commit: &'a CommitInfo, revision_url: String, compare_url: Option<String>) -> Self {
+        let date = format_date(&commit.time);
+        HistoryEntry {
+            commit,
+            date,
+            revision_url,
+            compare_url,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    view_url: &'a str,
+    blame_url: &'a str,
+    entries: Vec<HistoryEntry<'a>>,
+}
+
+impl<'a> Deref for HistoryTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+pub fn render_history(
+    title: &str,
+    view_url: &str,
+    blame_url: &str,
+    overview_url: &str,
+    entries: Vec<HistoryEntry<'_>>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
+    let template = HistoryTemplate {
+        layout: &layout,
+        view_url,
+        blame_url,
+        entries,
+    };
+    template.render()
+}
+
+pub struct BlameHunkView<'a> {
+    hunk: &'a BlameHunk,
+    date: String,
+    revision_url: String,
+}
+
+impl<'a> BlameHunkView<'a> {
+    pub fn new(hunk: &'a BlameHunk, revision_url: String) -> Self {
+        let date = format_date(&hunk.commit.time);
+        BlameHunkView {
+            hunk,
+            date,
+            revision_url,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "blame.html")]
+struct BlameTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    view_url: &'a str,
+    history_url: &'a str,
+    hunks: Vec<BlameHunkView<'a>>,
+}
+
+impl<'a> Deref for BlameTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+pub fn render_blame(
+    title: &str,
+    view_url: &str,
+    history_url: &str,
+    overview_url: &str,
+    hunks: Vec<BlameHunkView<'_>>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
+    let template = BlameTemplate {
+        layout: &layout,
+        view_url,
+        history_url,
+        hunks,
+    };
+    template.render()
+}
+
+pub struct DiffHunkView {
+    header: String,
+    rows: Vec<DiffRow>,
+}
+
+impl DiffHunkView {
+    pub fn new(hunk: &DiffHunk) -> Self {
+        DiffHunkView {
+            header: hunk.header.clone(),
+            rows: highlight_words(&hunk.lines),
+        }
+    }
+}
+
+/// One side of a comparison.
+pub struct CompareRevision<'a> {
+    commit: &'a CommitInfo,
+    date: String,
+    url: String,
+}
+
+impl<'a> CompareRevision<'a> {
+    pub fn new(commit: &'a CommitInfo, url: String) -> Self {
+        let date = format_date(&commit.time);
+        CompareRevision { commit, date, url }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+struct CompareTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    view_url: &'a str,
+    history_url: &'a str,
+    from: CompareRevision<'a>,
+    to: CompareRevision<'a>,
+    hunks: Vec<DiffHunkView>,
+}
+
+impl<'a> Deref for CompareTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_compare(
+    title: &str,
+    view_url: &str,
+    history_url: &str,
+    overview_url: &str,
+    from: CompareRevision<'_>,
+    to: CompareRevision<'_>,
+    hunks: Vec<DiffHunkView>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
+    let template = CompareTemplate {
+        layout: &layout,
+        view_url,
+        history_url,
+        from,
+        to,
+        hunks,
+    };
+    template.render()
+}
+
+pub struct LastSync {
+    date: String,
+    summary: String,
+    conflicts: Vec<String>,
+    failed: bool,
+}
+
+impl LastSync {
+    pub fn new(status: &SyncStatus) -> Self {
+        let date = format_date(&status.time);
+        let (summary, conflicts, failed) = match &status.result {
+            Ok(result) => {
+                let pulled = match &result.pull {
+                    PullResult::UpToDate => "Already up to date with the remote.".to_owned(),
+                    PullResult::FastForwarded => {
+                        format!("Pulled {} changed files.", result.changed_files.len())
+                    }
+                    PullResult::Merged => format!(
+                        "Merged changes from the remote, changing {} files.",
+                        result.changed_files.len()
+                    ),
+                    PullResult::Conflicted(paths) => {
+                        let summary = "Could not merge the changes from the remote.".to_owned();
+                        return LastSync {
+                            date,
+                            summary,
+                            conflicts: paths.clone(),
+                            failed: true,
+                        };
+                    }
+                };
+                let summary = if result.pushed {
+                    pulled + " Pushed local changes."
+                } else {
+                    pulled
+                };
+                (summary, Vec::new(), false)
+            }
+            Err(err) => (err.clone(), Vec::new(), true),
+        };
+        LastSync {
+            date,
+            summary,
+            conflicts,
+            failed,
+        }
+    }
+
+    fn succeeded(&self) -> bool {
+        !self.failed
+    }
+}
+
+#[derive(Template)]
+#[template(path = "sync_status.html")]
+struct SyncStatusTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    sync: Option<&'a SyncSettings>,
+    branch: Option<String>,
+    last_sync: Option<LastSync>,
+    post_url: &'a str,
+    authenticity_token: &'a str,
+}
+
+impl<'a> Deref for SyncStatusTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+pub fn render_sync_status(
+    sync: Option<&SyncSettings>,
+    branch: Option<String>,
+    last_sync: Option<LastSync>,
+    post_url: &str,
+    authenticity_token: &str,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new("Sync", "/overview", vec![], branch_switcher);
+    let template = SyncStatusTemplate {
+        layout: &layout,
+        sync,
+        branch,
+        last_sync,
+        post_url,
+        authenticity_token,
+    };
+    template.render()
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    message: &'a str,
+    hints: &'a [&'a str],
+}
+
+impl<'a> Deref for ErrorTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+pub fn render_error(title: &str, message: &str, hints: &[&str]) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, "/overview", vec![], None);
+    let template = ErrorTemplate {
+        layout: &layout,
+        message,
+        hints,
+    };
+    template.render()
+}
+
 #[derive(Template)]
 #[template(path = "page_placeholder.html")]
 struct PagePlaceholderTemplate<'a> {
@@ -107,8 +548,9 @@ pub fn render_page_placeholder(
     create_url: &str,
     overview_url: &str,
     breadcrumbs: Vec<Breadcrumb<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
 ) -> askama::Result<String> {
-    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs);
+    let layout = LayoutTemplate::new(title, overview_url, breadcrumbs, branch_switcher);
     let template = PagePlaceholderTemplate {
         layout: &layout,
         file_path,
@@ -125,8 +567,14 @@ struct EditTemplate<'a> {
     post_url: &'a str,
     view_url: &'a str,
     preview_url: &'a str,
+    upload_url: &'a str,
     message_placeholder: Option<String>,
+    message: &'a str,
+    author_name: &'a str,
+    author_email: &'a str,
     content: &'a str,
+    base_revision: Option<&'a str>,
+    conflict_hunks: Option<Vec<DiffHunkView>>,
     authenticity_token: &'a str,
 }
 
@@ -144,19 +592,116 @@ pub fn render_edit_page(
     post_url: &str,
     view_url: &str,
     preview_url: &str,
+    upload_url: &str,
     message_placeholder: Option<String>,
+    message: &str,
+    author_name: &str,
+    author_email: &str,
     content: &str,
+    base_revision: Option<&str>,
+    conflict_hunks: Option<Vec<DiffHunkView>>,
     breadcrumbs: Vec<Breadcrumb<'_>>,
     authenticity_token: &str,
+    branch_switcher: Option<BranchSwitcher>,
 ) -> askama::Result<String> {
-    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs);
+    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs, branch_switcher);
     let template = EditTemplate {
         layout: &layout,
         post_url,
         view_url,
         preview_url,
+        upload_url,
         message_placeholder,
+        message,
+        author_name,
+        author_email,
         content,
+        base_revision,
+        conflict_hunks,
+        authenticity_token,
+    };
+    template.render()
+}
+
+#[derive(Template)]
+#[template(path = "rename_page.html")]
+struct RenameTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    post_url: &'a str,
+    view_url: &'a str,
+    current_path: &'a str,
+    message_placeholder: Option<String>,
+    authenticity_token: &'a str,
+}
+
+impl<'a> Deref for RenameTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_rename_page(
+    title: &str,
+    post_url: &str,
+    view_url: &str,
+    current_path: &str,
+    message_placeholder: Option<String>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    authenticity_token: &str,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs, branch_switcher);
+    let template = RenameTemplate {
+        layout: &layout,
+        post_url,
+        view_url,
+        current_path,
+        message_placeholder,
+        authenticity_token,
+    };
+    template.render()
+}
+
+#[derive(Template)]
+#[template(path = "delete_page.html")]
+struct DeleteTemplate<'a> {
+    layout: &'a LayoutTemplate<'a>,
+    post_url: &'a str,
+    view_url: &'a str,
+    file_path: &'a str,
+    message_placeholder: Option<String>,
+    authenticity_token: &'a str,
+}
+
+impl<'a> Deref for DeleteTemplate<'a> {
+    type Target = LayoutTemplate<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.layout
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_delete_page(
+    title: &str,
+    post_url: &str,
+    view_url: &str,
+    file_path: &str,
+    message_placeholder: Option<String>,
+    breadcrumbs: Vec<Breadcrumb<'_>>,
+    authenticity_token: &str,
+    branch_switcher: Option<BranchSwitcher>,
+) -> askama::Result<String> {
+    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs, branch_switcher);
+    let template = DeleteTemplate {
+        layout: &layout,
+        post_url,
+        view_url,
+        file_path,
+        message_placeholder,
         authenticity_token,
     };
     template.render()
@@ -196,8 +741,9 @@ pub fn render_overview(
     breadcrumbs: Vec<Breadcrumb<'_>>,
     directories: Vec<DirectoryEntry<'_>>,
     files: Vec<DirectoryEntry<'_>>,
+    branch_switcher: Option<BranchSwitcher>,
 ) -> askama::Result<String> {
-    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs);
+    let layout = LayoutTemplate::new(title, "/overview", breadcrumbs, branch_switcher);
     let file_svg = include_str!("../static/file.svg");
     let file_directory_svg = include_str!("../static/file_directory.svg");
     let template = OverviewTemplate {
@@ -218,6 +764,7 @@ struct SearchResultsTemplate<'a> {
     documents: Vec<SearchResult>,
     prev_url: Option<String>,
     next_url: Option<String>,
+    indexing: Option<IndexingProgress>,
 }
 
 impl<'a> Deref for SearchResultsTemplate<'a> {
@@ -233,15 +780,18 @@ pub fn render_search_results(
     documents: Vec<SearchResult>,
     prev_url: Option<String>,
     next_url: Option<String>,
+    indexing: Option<IndexingProgress>,
+    branch_switcher: Option<BranchSwitcher>,
 ) -> askama::Result<String> {
     let breadcrumbs = vec![];
-    let layout = LayoutTemplate::new("Search results", "/overview", breadcrumbs);
+    let layout = LayoutTemplate::new("Search results", "/overview", breadcrumbs, branch_switcher);
     let template = SearchResultsTemplate {
         layout: &layout,
         query,
         documents,
         prev_url,
         next_url,
+        indexing,
     };
     template.render()
 }
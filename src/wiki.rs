@@ -1,27 +1,60 @@
+use std::cell::OnceCell;
+use std::cell::RefCell;
+use std::num::NonZeroUsize;
+use std::path::Path;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
+use lru::LruCache;
 
+use rayon::prelude::*;
 use regex::bytes::{Captures, Regex, RegexBuilder};
 
+use tantivy::collector::DocSetCollector;
 use tantivy::collector::TopDocs;
 use tantivy::query::QueryParser;
+use tantivy::query::TermQuery;
 use tantivy::schema::*;
 use tantivy::snippet::Snippet;
 use tantivy::snippet::SnippetGenerator;
 use tantivy::DocAddress;
 use tantivy::Index;
+use tantivy::IndexReader;
 use tantivy::IndexWriter;
 use tantivy::ReloadPolicy;
 use tantivy::Score;
+use time::OffsetDateTime;
 
 use crate::error::MyError;
+use crate::links::find_page_links;
+use crate::links::new_page_link;
+use crate::links::resolve_link;
+use crate::links::rewrite_for_rename;
+use crate::links::WikiLinkTargets;
+use crate::merge::merge3;
+use crate::page::get_page;
 use crate::page::get_raw_page;
 use crate::page::is_page;
+use crate::page::LinkTarget;
+use crate::page::Page;
+use crate::page::PageLink;
+use crate::repository::Author;
+use crate::repository::BlameHunk;
+use crate::repository::CommitInfo;
+use crate::repository::DiffHunk;
+use crate::repository::FileEntry;
 use crate::repository::RepoBox;
 use crate::repository::RepositoryCapability;
 use crate::repository::RepositoryItem;
+use crate::repository::RepositorySnapshot;
+use crate::repository::SyncResult;
+use crate::repository::UpdatedFile;
 use crate::settings::Settings;
 
 /// Wiki god object.
@@ -29,6 +62,21 @@ struct WikiInner {
     settings: Settings,
     repository: RepoBox,
     index: Index,
+    /// The only writer, kept open for as long as the wiki runs since only one can be open at a
+    /// time. Held while bringing the index up to date.
+    index_writer: Mutex<IndexWriter>,
+    /// Shared by all searches, and reloaded whenever the index is committed.
+    index_reader: IndexReader,
+    /// Files changed through the wiki, to reindex in the background.
+    index_updates: mpsc::Sender<Vec<String>>,
+    /// Set until the index has been brought up to date with the repository for the first time.
+    indexing: Mutex<Option<Indexing>>,
+    /// Held while syncing, so that only one sync runs at a time.
+    sync_lock: Mutex<()>,
+    last_sync: Mutex<Option<SyncStatus>>,
+    page_cache: Mutex<LruCache<PageCacheKey, CachedPage>>,
+    /// What `[[wiki links]]` can point to, as of the commit it was last needed for.
+    link_targets: Mutex<Option<(String, Arc<WikiLinkTargets>)>>,
 }
 
 // TODO: is there are away to share immutable global without the reference counting? A 'static lifetime somehow?
@@ -44,19 +92,90 @@ impl std::fmt::Debug for Wiki {
 struct SearchFields {
     title: Field,
     path: Field,
+    /// Untokenized copy of the path, used to find the document for a file when it changes.
+    path_key: Field,
     body: Field,
+    /// Fields from the front matter, which can be searched for with `tags:name` and so on.
+    tags: Field,
+    aliases: Field,
+    description: Field,
+    draft: Field,
+    /// The paths of the files a page links to, used to find the pages linking to a file.
+    links: Field,
 }
 
 impl SearchFields {
     fn from_schema(schema: &Schema) -> Self {
         let title = schema.get_field("title").unwrap();
         let path = schema.get_field("path").unwrap();
+        let path_key = schema.get_field("path_key").unwrap();
         let body = schema.get_field("body").unwrap();
+        let tags = schema.get_field("tags").unwrap();
+        let aliases = schema.get_field("aliases").unwrap();
+        let description = schema.get_field("description").unwrap();
+        let draft = schema.get_field("draft").unwrap();
+        let links = schema.get_field("links").unwrap();
 
-        SearchFields { title, path, body }
+        SearchFields {
+            title,
+            path,
+            path_key,
+            body,
+            tags,
+            aliases,
+            description,
+            draft,
+            links,
+        }
     }
 }
 
+/// How far along bringing the search index up to date is.
+#[derive(Clone, Copy, Default)]
+pub struct IndexingProgress {
+    pub indexed: usize,
+    pub total: usize,
+}
+
+#[derive(Default)]
+struct Indexing {
+    progress: IndexingProgress,
+    /// Files changed through the wiki while indexing, to reindex once it is done.
+    pending: Vec<String>,
+}
+
+/// How many rendered pages to keep in memory.
+const PAGE_CACHE_SIZE: NonZeroUsize = NonZeroUsize::new(256).unwrap();
+
+/// Identifies a rendered page. Blob IDs change whenever the contents do, so an edited page gets
+/// a new entry instead of the old one having to be thrown away.
+#[derive(PartialEq, Eq, Hash)]
+struct PageCacheKey {
+    path: String,
+    blob_id: String,
+    /// The only setting that changes how pages are rendered.
+    h1_title: bool,
+}
+
+/// The paths and blob IDs of the files expanded into a page.
+type Transclusions = Vec<(String, Option<String>)>;
+
+#[derive(Clone)]
+struct CachedPage {
+    /// Must still match for the page to be reused.
+    transcluded: Transclusions,
+    /// The links on the page that were looked up and where they went, which must still resolve
+    /// the same way for the page to be reused.
+    links: Vec<(PageLink, Option<LinkTarget>)>,
+    page: Page,
+}
+
+/// A page that links to or transcludes another file.
+pub struct Backlink {
+    pub title: String,
+    pub path: String,
+}
+
 pub struct SearchResult {
     pub score: f32,
     pub title: String,
@@ -64,118 +183,416 @@ pub struct SearchResult {
     pub snippet_html: String,
 }
 
-fn index_directory(
-    settings: &Settings,
-    repository: &RepoBox,
-    index_writer: &mut IndexWriter,
-    search_fields: &SearchFields,
-    dir: Vec<&str>,
-) -> Result<(), MyError> {
-    for item in repository.enumerate_files(&dir)? {
-        match item {
-            RepositoryItem::Directory(subdir) => {
-                let mut subdir_path = dir.clone();
-                subdir_path.push(&subdir);
-                index_directory(
-                    settings,
-                    repository,
-                    index_writer,
-                    search_fields,
-                    subdir_path,
-                )?;
+/// The outcome of the most recent sync with the remote.
+#[derive(Clone)]
+pub struct SyncStatus {
+    pub time: OffsetDateTime,
+    pub result: Result<SyncResult, String>,
+}
+
+pub enum EditResult {
+    Saved,
+    /// The file was changed since the edit started, and the changes overlap.
+    Conflict {
+        /// The edit merged with the current version, with conflict markers around the overlaps.
+        content: String,
+        /// The commit the merged content is based on.
+        head: CommitInfo,
+    },
+}
+
+/// The wiki as of one commit, so that everything read while handling a request, including
+/// transcluded files, comes from the same revision.
+pub struct Snapshot<'a> {
+    wiki: &'a WikiInner,
+    files: Box<dyn RepositorySnapshot + 'a>,
+    link_targets: OnceCell<Arc<WikiLinkTargets>>,
+}
+
+impl Snapshot<'_> {
+    /// The commit being read, for repositories with history.
+    pub fn commit_id(&self) -> Option<&str> {
+        self.files.commit_id()
+    }
+
+    pub fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
+        read_with_transclusions(file_path, |path| self.files.read_file(path))
+    }
+
+    /// Reads a file as stored, without expanding transclusions.
+    pub fn read_raw_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
+        self.files.read_file(file_path)
+    }
+
+    pub fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        self.files.directory_exists(path)
+    }
+
+    pub fn file_exists(&self, path: &[&str]) -> Result<bool, MyError> {
+        self.files.file_exists(path)
+    }
+
+    pub fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
+        self.files.enumerate_files(directory)
+    }
+
+    /// Looks up where a link on a page in `base_dir` goes. Markdown links are only looked up if
+    /// they point to a page in the wiki that does not exist.
+    pub fn resolve_link(&self, base_dir: &[&str], link: &PageLink) -> Option<LinkTarget> {
+        match link {
+            PageLink::Wiki(name) => {
+                let targets = self.link_targets();
+                Some(LinkTarget {
+                    url: targets.url(base_dir, name),
+                    exists: targets.resolve(base_dir, name).is_some(),
+                })
             }
-            RepositoryItem::File(file_name) => {
-                if let Some((file_stem, file_ext)) = file_name.rsplit_once('.') {
-                    if is_page(file_ext) {
-                        let mut path = dir.clone();
-                        path.push(&file_name);
-                        let bytes = match repository.read_file(&path) {
-                            Ok(bytes) => bytes,
-                            Err(err) => {
-                                println!(
-                                    "Failed to open file '{}' for indexing: {:?}",
-                                    path.join("/"),
-                                    err
-                                );
-                                continue;
-                            }
-                        };
-                        match get_raw_page(file_stem, file_ext, &bytes, settings) {
-                            Ok(Some(page)) => {
-                                index_file(&path, search_fields, page, index_writer);
-                            }
-                            Ok(None) => {
-                                unreachable!(
-                                    "We should have already checked to see if this was a page."
-                                );
-                            }
-                            Err(MyError::BadUtf8 { source }) => {
-                                println!(
-                                    "Bad UTF-8 in file, not indexing. See byte position {} in {}",
-                                    source.valid_up_to(),
-                                    path.join("/")
-                                );
-                            }
-                            Err(err) => {
-                                println!(
-                                    "Failed to parse file '{}' for indexing: {}",
-                                    path.join("/"),
-                                    err
-                                );
-                            }
-                        }
-                    }
+            PageLink::Markdown(dest) => {
+                let target = resolve_link(base_dir, dest)?;
+                let (_, ext) = target.last()?.rsplit_once('.')?;
+                let path: Vec<&str> = target.iter().map(|s| s.as_str()).collect();
+                if !is_page(ext) || self.file_exists(&path).unwrap_or(true) {
+                    return None;
                 }
+                Some(LinkTarget {
+                    url: new_page_link(&target),
+                    exists: false,
+                })
             }
         }
     }
-    Ok(())
+
+    /// Lists the files wiki links can point to the first time it is needed. The list is shared
+    /// with other snapshots of the same commit.
+    fn link_targets(&self) -> &WikiLinkTargets {
+        self.link_targets.get_or_init(|| {
+            let commit_id = self.files.commit_id();
+            let mut shared = self.wiki.link_targets.lock().unwrap();
+            if let (Some(commit_id), Some((shared_id, targets))) = (commit_id, shared.as_ref()) {
+                if commit_id == shared_id {
+                    return Arc::clone(targets);
+                }
+            }
+            let files = self.files.list_files().unwrap_or_default();
+            let targets = Arc::new(WikiLinkTargets::new(files.iter().map(|f| f.path.as_str())));
+            if let Some(commit_id) = commit_id {
+                *shared = Some((commit_id.to_owned(), Arc::clone(&targets)));
+            }
+            targets
+        })
+    }
+
+    /// Like `read_file`, and also returns the path and ID of every file that was transcluded.
+    fn read_file_and_transclusions(
+        &self,
+        file_path: &[&str],
+    ) -> Result<(Vec<u8>, Transclusions), MyError> {
+        // The first read is of the file itself.
+        let reads = RefCell::new(Vec::new());
+        let bytes = read_with_transclusions(file_path, |path| {
+            reads
+                .borrow_mut()
+                .push((path.join("/"), self.files.file_id(path)));
+            self.files.read_file(path)
+        })?;
+        let transcluded = reads.into_inner().split_off(1);
+        Ok((bytes, transcluded))
+    }
 }
 
-fn index_file(
-    path: &[&str],
+/// Reads a file, expanding any `{{transclusions}}` using the same `read` function.
+fn read_with_transclusions<F>(file_path: &[&str], read: F) -> Result<Vec<u8>, MyError>
+where
+    F: Fn(&[&str]) -> Result<Vec<u8>, MyError>,
+{
+    lazy_static! {
+        static ref RE: Regex = RegexBuilder::new(r"^\{\{(.+?)\}\}$")
+            .multi_line(true)
+            .build()
+            .unwrap();
+    }
+    let mut res = read(file_path);
+    if let Ok(mut bytes) = res {
+        while RE.is_match(&bytes) {
+            bytes = RE
+                .replace(&bytes, |caps: &Captures| {
+                    if let Ok(filename) = str::from_utf8(&caps[1]) {
+                        let path: Vec<&str> = filename.split('/').collect();
+                        read(&path).unwrap_or(b"**read error**".to_vec())
+                    } else {
+                        b"**conversion error**".to_vec()
+                    }
+                })
+                .to_vec();
+        }
+        res = Ok(bytes)
+    }
+    res
+}
+
+/// Lists every file in the wiki.
+fn all_files(snapshot: &Snapshot) -> Result<Vec<Vec<String>>, MyError> {
+    let mut ret = Vec::new();
+    let mut dirs = vec![vec![]];
+    while let Some(dir) = dirs.pop() {
+        let dir_refs: Vec<&str> = dir.iter().map(|s: &String| s.as_str()).collect();
+        for item in snapshot.enumerate_files(&dir_refs)? {
+            let (RepositoryItem::Directory(name) | RepositoryItem::File(name)) = &item;
+            let mut path = dir.clone();
+            path.push(name.clone());
+            match item {
+                RepositoryItem::Directory(_) => dirs.push(path),
+                RepositoryItem::File(_) => ret.push(path),
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn is_page_path(path: &str) -> bool {
+    path.rsplit_once('.')
+        .is_some_and(|(_, file_ext)| is_page(file_ext))
+}
+
+/// Reindexes a file given its current contents, or removes it from the index if it no longer
+/// exists. Pages that cannot be parsed are left out of the index.
+fn reindex_file(
+    settings: &Settings,
+    index_writer: &IndexWriter,
     search_fields: &SearchFields,
-    page: crate::page::Page,
-    index_writer: &mut IndexWriter,
+    wiki_link_targets: &WikiLinkTargets,
+    path: &[&str],
+    content: Option<&[u8]>,
 ) {
+    index_writer.delete_term(Term::from_field_text(
+        search_fields.path_key,
+        &path_to_url(path),
+    ));
+    let Some((file_stem, file_ext)) = path.last().and_then(|name| name.rsplit_once('.')) else {
+        return;
+    };
+    let Some(content) = content.filter(|_| is_page(file_ext)) else {
+        return;
+    };
+    match get_raw_page(file_stem, file_ext, content, settings) {
+        Ok(Some(page)) => {
+            let base_dir = &path[..path.len() - 1];
+            let links = find_page_links(base_dir, &page.body, wiki_link_targets);
+            index_file(path, search_fields, page, &links, index_writer)
+        }
+        Ok(None) => {}
+        Err(MyError::BadUtf8 { source }) => {
+            println!(
+                "Bad UTF-8 in file, not indexing. See byte position {} in {}",
+                source.valid_up_to(),
+                path.join("/")
+            );
+        }
+        Err(err) => {
+            println!(
+                "Failed to parse file '{}' for indexing: {}",
+                path.join("/"),
+                err
+            );
+        }
+    }
+}
+
+/// The value stored in the path field of the search index.
+fn path_to_url(path: &[&str]) -> String {
     let mut url = String::new();
     for path in path {
         url += "/";
         url += path;
     }
+    url
+}
+
+fn index_file(
+    path: &[&str],
+    search_fields: &SearchFields,
+    page: crate::page::Page,
+    links: &[Vec<String>],
+    index_writer: &IndexWriter,
+) {
+    let url = path_to_url(path);
     let mut doc = TantivyDocument::default();
     doc.add_text(search_fields.path, &url);
+    doc.add_text(search_fields.path_key, &url);
     doc.add_text(search_fields.title, page.title);
     doc.add_text(search_fields.body, page.body);
-    index_writer.delete_term(Term::from_field_text(search_fields.path, &url));
+    let front_matter = page.front_matter;
+    for tag in front_matter.tags {
+        doc.add_text(search_fields.tags, tag);
+    }
+    for alias in front_matter.aliases {
+        doc.add_text(search_fields.aliases, alias);
+    }
+    if let Some(description) = front_matter.description {
+        doc.add_text(search_fields.description, description);
+    }
+    doc.add_bool(search_fields.draft, front_matter.draft);
+    for link in links.iter().filter(|link| *link != path) {
+        let link: Vec<&str> = link.iter().map(|s| s.as_str()).collect();
+        doc.add_text(search_fields.links, path_to_url(&link));
+    }
+    index_writer.delete_term(Term::from_field_text(search_fields.path_key, &url));
     index_writer.add_document(doc).unwrap();
 }
 
 const INDEXING_HEAP_SIZE: usize = 50_000_000;
 
-fn create_index(settings: &Settings, repository: &RepoBox) -> Result<Index, MyError> {
+/// Increment when changing how pages are turned into documents, so that indexes stored by older
+/// versions are rebuilt.
+const INDEX_FORMAT_VERSION: u32 = 2;
+
+/// What the documents in the index were built from. Stored in the payload of each index commit,
+/// so that an index kept on disk can be brought up to date when the wiki starts.
+#[derive(Debug, PartialEq, Eq)]
+struct IndexState {
+    version: u32,
+    h1_title: bool,
+    /// The commit the files were read from. `None` if the repository has no commits, such as
+    /// when reading from the file system.
+    commit: Option<String>,
+}
+
+impl IndexState {
+    fn current(settings: &Settings, repository: &RepoBox) -> Self {
+        IndexState {
+            version: INDEX_FORMAT_VERSION,
+            h1_title: settings.h1_title(),
+            commit: repository.head_commit().ok().map(|commit| commit.id),
+        }
+    }
+
+    fn to_payload(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.version,
+            self.h1_title,
+            self.commit.as_deref().unwrap_or("-")
+        )
+    }
+
+    fn from_payload(payload: &str) -> Option<Self> {
+        let mut parts = payload.split(' ');
+        let version = parts.next()?.parse().ok()?;
+        let h1_title = parts.next()?.parse().ok()?;
+        let commit = match parts.next()? {
+            "-" => None,
+            commit => Some(commit.to_owned()),
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(IndexState {
+            version,
+            h1_title,
+            commit,
+        })
+    }
+}
+
+/// How many times to redo a change on top of the latest commit before giving up, when other
+/// commits keep moving the branch in the meantime.
+const COMMIT_ATTEMPTS: usize = 3;
+
+/// How long to wait for more changes before reindexing, so that they are committed together.
+const INDEX_BATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// Reindexes the files changed through the wiki in batches, until the wiki is dropped.
+fn run_index_updates(wiki: Weak<WikiInner>, updates: mpsc::Receiver<Vec<String>>) {
+    while let Ok(first) = updates.recv() {
+        std::thread::sleep(INDEX_BATCH_DELAY);
+        let mut changed = first;
+        for paths in updates.try_iter() {
+            for path in paths {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+        let Some(wiki) = wiki.upgrade() else {
+            return;
+        };
+        let paths = split_paths(&changed);
+        let paths: Vec<&[&str]> = paths.iter().map(|p| p.as_slice()).collect();
+        if let Err(err) = Wiki(wiki).update_index(&paths) {
+            eprintln!("Failed to update the search index: {}", err);
+        }
+    }
+}
+
+fn index_schema() -> Schema {
     let mut schema_builder = Schema::builder();
     schema_builder.add_text_field("title", TEXT | STORED);
     schema_builder.add_text_field("path", TEXT | STORED);
+    schema_builder.add_text_field("path_key", STRING);
     schema_builder.add_text_field("body", TEXT | STORED);
-    let schema = schema_builder.build();
-    //TODO: store on disk?
-    let index = Index::create_in_ram(schema.clone());
+    schema_builder.add_text_field("tags", TEXT | STORED);
+    schema_builder.add_text_field("aliases", TEXT | STORED);
+    schema_builder.add_text_field("description", TEXT | STORED);
+    schema_builder.add_bool_field("draft", INDEXED | STORED);
+    schema_builder.add_text_field("links", STRING);
+    schema_builder.build()
+}
+
+/// Opens the index stored in `dir`. Starts over with an empty index if there is none yet, or if
+/// the stored one has a different schema or cannot be read.
+fn open_index(dir: &Path, schema: Schema) -> Result<Index, MyError> {
+    if dir.join("meta.json").exists() {
+        match Index::open_in_dir(dir) {
+            Ok(index) if index.schema() == schema => return Ok(index),
+            Ok(_) => println!("The search index schema changed, rebuilding it."),
+            Err(err) => println!("Failed to open the search index, rebuilding it: {}", err),
+        }
+    }
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    Ok(Index::create_in_dir(dir, schema)?)
+}
 
-    let mut index_writer = index.writer(INDEXING_HEAP_SIZE)?;
-    let search_fields = SearchFields::from_schema(&schema);
+/// Finds the files to reindex to bring an index built from `stored` up to date with `current`.
+/// Returns `None` if everything needs to be reindexed.
+fn files_changed_since(
+    repository: &RepoBox,
+    stored: &IndexState,
+    current: &IndexState,
+) -> Option<Vec<String>> {
+    if stored.version != current.version || stored.h1_title != current.h1_title {
+        return None;
+    }
+    let (Some(from), Some(to)) = (&stored.commit, &current.commit) else {
+        return None;
+    };
+    if from == to {
+        return Some(Vec::new());
+    }
+    repository.changed_files(from, to).ok()
+}
 
-    println!("Indexing files, this can take a while if there are a lot.");
-    index_directory(
-        settings,
-        repository,
-        &mut index_writer,
-        &search_fields,
-        vec![],
-    )?;
-    index_writer.commit()?;
+/// The state recorded by the last commit to the index, if it was made by this version.
+fn stored_index_state(index: &Index) -> Result<Option<IndexState>, MyError> {
+    Ok(index
+        .load_metas()?
+        .payload
+        .as_deref()
+        .and_then(IndexState::from_payload))
+}
 
-    Ok(index)
+fn split_paths(paths: &[String]) -> Vec<Vec<&str>> {
+    paths.iter().map(|path| path.split('/').collect()).collect()
+}
+
+fn create_index(settings: &Settings) -> Result<Index, MyError> {
+    let schema = index_schema();
+    match settings.index_dir() {
+        Some(dir) => open_index(dir, schema),
+        None => Ok(Index::create_in_ram(schema)),
+    }
 }
 
 // TODO: this does not belong at all in the Wiki, it belongs more in request handling
@@ -199,13 +616,30 @@ fn highlight(snippet: Snippet) -> String {
 
 impl Wiki {
     pub fn new(settings: Settings, repository: RepoBox) -> Result<Self, MyError> {
-        let index = create_index(&settings, &repository)?;
+        let index = create_index(&settings)?;
+        let index_writer = index.writer(INDEXING_HEAP_SIZE)?;
+        let index_reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let (index_updates, updates) = mpsc::channel();
         let inner = WikiInner {
             settings,
             repository,
             index,
+            index_writer: Mutex::new(index_writer),
+            index_reader,
+            index_updates,
+            indexing: Mutex::new(Some(Indexing::default())),
+            sync_lock: Mutex::new(()),
+            last_sync: Mutex::new(None),
+            page_cache: Mutex::new(LruCache::new(PAGE_CACHE_SIZE)),
+            link_targets: Mutex::new(None),
         };
-        Ok(Wiki(Arc::from(inner)))
+        let wiki = Wiki(Arc::from(inner));
+        let weak = Arc::downgrade(&wiki.0);
+        std::thread::spawn(move || run_index_updates(weak, updates));
+        Ok(wiki)
     }
 
     pub fn settings(&self) -> &Settings {
@@ -216,56 +650,643 @@ impl Wiki {
         self.0.repository.capabilities()
     }
 
-    pub fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
-        lazy_static! {
-            static ref RE: Regex = RegexBuilder::new(r"^\{\{(.+?)\}\}$")
-                .multi_line(true)
-                .build()
-                .unwrap();
-        }
-        let mut res = self.0.repository.read_file(file_path);
-        if let Ok(mut bytes) = res {
-            while RE.is_match(&bytes) {
-                bytes = RE
-                    .replace(&bytes, |caps: &Captures| {
-                        if let Ok(filename) = str::from_utf8(&caps[1]) {
-                            self.0
-                                .repository
-                                .read_file(&[filename])
-                                .unwrap_or(b"**read error**".to_vec())
-                        } else {
-                            b"**conversion error**".to_vec()
-                        }
-                    })
-                    .to_vec();
+    /// Pins the files being served for the rest of a request.
+    pub fn snapshot(&self) -> Result<Snapshot<'_>, MyError> {
+        Ok(Snapshot {
+            wiki: &self.0,
+            files: self.0.repository.snapshot()?,
+            link_targets: OnceCell::new(),
+        })
+    }
+
+    /// The wiki as of an older revision, such as a commit ID or tag.
+    pub fn snapshot_at(&self, revision: &str) -> Result<Snapshot<'_>, MyError> {
+        Ok(Snapshot {
+            wiki: &self.0,
+            files: self.0.repository.snapshot_at(revision)?,
+            link_targets: OnceCell::new(),
+        })
+    }
+
+    /// Renders a page, reusing an earlier rendering if neither the page nor the files it
+    /// transcludes changed since. Returns `None` for files that are not pages.
+    pub fn render_page(
+        &self,
+        snapshot: &Snapshot,
+        file_path: &[&str],
+    ) -> Result<Option<Page>, MyError> {
+        let Some((file_stem, file_ext)) = file_path.last().and_then(|name| name.rsplit_once('.'))
+        else {
+            return Ok(None);
+        };
+        if !is_page(file_ext) {
+            return Ok(None);
+        }
+        let base_dir = &file_path[..file_path.len() - 1];
+
+        // Without blob IDs there is no cheap way to tell whether a page changed.
+        let key = snapshot
+            .files
+            .file_id(file_path)
+            .map(|blob_id| PageCacheKey {
+                path: file_path.join("/"),
+                blob_id,
+                h1_title: self.0.settings.h1_title(),
+            });
+        if let Some(key) = &key {
+            let cached = self.0.page_cache.lock().unwrap().get(key).cloned();
+            if let Some(cached) = cached {
+                if cached
+                    .transcluded
+                    .iter()
+                    .all(|(path, id)| snapshot.files.file_id(&[path]) == *id)
+                    && cached
+                        .links
+                        .iter()
+                        .all(|(link, target)| snapshot.resolve_link(base_dir, link) == *target)
+                {
+                    return Ok(Some(cached.page));
+                }
             }
-            res = Ok(bytes)
         }
-        res
+
+        let (bytes, transcluded) = snapshot.read_file_and_transclusions(file_path)?;
+        let links = RefCell::new(Vec::new());
+        let resolve = |link: &PageLink| {
+            let target = snapshot.resolve_link(base_dir, link);
+            links.borrow_mut().push((link.clone(), target.clone()));
+            target
+        };
+        let page = get_page(file_stem, file_ext, &bytes, &self.0.settings, &resolve)?;
+        if let (Some(key), Some(page)) = (key, &page) {
+            let cached = CachedPage {
+                transcluded,
+                links: links.into_inner(),
+                page: page.clone(),
+            };
+            self.0.page_cache.lock().unwrap().put(key, cached);
+        }
+        Ok(page)
     }
 
+    pub fn get_commit(&self, revision: &str) -> Result<CommitInfo, MyError> {
+        self.0.repository.get_commit(revision)
+    }
+
+    pub fn branches(&self) -> Result<Vec<String>, MyError> {
+        self.0.repository.branches()
+    }
+
+    pub fn current_branch(&self) -> Option<String> {
+        self.0.repository.current_branch()
+    }
+
+    /// Serves a different branch and brings the search index up to date with it in the
+    /// background.
+    pub fn switch_branch(&self, name: &str) -> Result<(), MyError> {
+        self.0.repository.set_branch(name)?;
+        self.0
+            .indexing
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Indexing::default);
+        self.start_indexing();
+        Ok(())
+    }
+
+    pub fn blame(&self, file_path: &[&str]) -> Result<Vec<BlameHunk>, MyError> {
+        self.0.repository.blame(file_path)
+    }
+
+    pub fn diff_file(
+        &self,
+        file_path: &[&str],
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<DiffHunk>, MyError> {
+        self.0.repository.diff_file(file_path, from, to)
+    }
+
+    /// Writes a file, committing it as `author` if given and as the configured author otherwise.
     pub fn write_file(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let author = author.or(self.0.settings.author());
+        self.0
+            .repository
+            .write_file(file_path, message, content, author)?;
+        self.queue_index_update(&[file_path]);
+        self.committed();
+        Ok(())
+    }
+
+    /// Writes a file in a commit on top of `parent`. See `Repository::write_file_on`.
+    fn write_file_on(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &[u8],
+        parent: &str,
+        author: Option<&Author>,
+    ) -> Result<(), MyError> {
+        let author = author.or(self.0.settings.author());
+        self.0
+            .repository
+            .write_file_on(file_path, message, content, parent, author)?;
+        self.queue_index_update(&[file_path]);
+        self.committed();
+        Ok(())
+    }
+
+    /// Saves an edit that was started from the file as of `base_revision`.
+    ///
+    /// If the file has been changed since then, the changes are merged with the edit. When they
+    /// overlap, nothing is saved and the merged content with conflict markers is returned instead.
+    pub fn save_edit(
         &self,
         file_path: &[&str],
         message: &str,
         content: &str,
+        base_revision: Option<&str>,
+        author: Option<&Author>,
+    ) -> Result<EditResult, MyError> {
+        // Browsers submit text areas with CRLF line endings, which would make every line differ
+        // from the stored file when merging.
+        let content = &content.replace("\r\n", "\n");
+        let Some(base_revision) = base_revision else {
+            self.write_file(file_path, message, content.as_bytes(), author)?;
+            return Ok(EditResult::Saved);
+        };
+        // Commits made while merging move the branch, and the merge starts over from there.
+        for _ in 1..COMMIT_ATTEMPTS {
+            match self.try_save_edit(file_path, message, content, base_revision, author) {
+                Err(MyError::HeadMoved) => continue,
+                res => return res,
+            }
+        }
+        self.try_save_edit(file_path, message, content, base_revision, author)
+    }
+
+    /// Merges an edit with the file as of the branch's latest commit, and commits the result on
+    /// top of that same commit.
+    fn try_save_edit(
+        &self,
+        file_path: &[&str],
+        message: &str,
+        content: &str,
+        base_revision: &str,
+        author: Option<&Author>,
+    ) -> Result<EditResult, MyError> {
+        let head = self.0.repository.head_commit()?;
+        if head.id == base_revision {
+            self.write_file_on(file_path, message, content.as_bytes(), &head.id, author)?;
+            return Ok(EditResult::Saved);
+        }
+
+        // A missing file reads as empty, so that pages created or deleted in the meantime are
+        // merged like any other change.
+        let read_at =
+            |revision: &str| match self.0.repository.read_file_at_revision(file_path, revision) {
+                Err(MyError::InvalidPath) => Ok(Vec::new()),
+                res => res,
+            };
+        let base = read_at(base_revision)?;
+        let current = read_at(&head.id)?;
+        if base == current {
+            self.write_file_on(file_path, message, content.as_bytes(), &head.id, author)?;
+            return Ok(EditResult::Saved);
+        }
+
+        let merged = merge3(str::from_utf8(&base)?, content, str::from_utf8(&current)?);
+        if merged.conflicts > 0 {
+            return Ok(EditResult::Conflict {
+                content: merged.content,
+                head,
+            });
+        }
+        if merged.content.as_bytes() != current {
+            self.write_file_on(
+                file_path,
+                message,
+                merged.content.as_bytes(),
+                &head.id,
+                author,
+            )?;
+        }
+        Ok(EditResult::Saved)
+    }
+
+    /// Moves a file and rewrites the links on every page that pointed to it, in one commit.
+    pub fn rename_file(
+        &self,
+        old_path: &[&str],
+        new_path: &[&str],
+        message: &str,
+    ) -> Result<(), MyError> {
+        // Pages changed while their links were being rewritten start the rename over.
+        for _ in 1..COMMIT_ATTEMPTS {
+            match self.try_rename_file(old_path, new_path, message) {
+                Err(MyError::HeadMoved) => continue,
+                res => return res,
+            }
+        }
+        self.try_rename_file(old_path, new_path, message)
+    }
+
+    /// Rewrites the links as of the branch's latest commit, and commits the rename on top of that
+    /// same commit.
+    fn try_rename_file(
+        &self,
+        old_path: &[&str],
+        new_path: &[&str],
+        message: &str,
+    ) -> Result<(), MyError> {
+        let (Some((_, old_dir)), Some((_, new_dir))) =
+            (old_path.split_last(), new_path.split_last())
+        else {
+            return Err(MyError::InvalidPath);
+        };
+        let snapshot = self.snapshot()?;
+        if !snapshot.file_exists(old_path)? {
+            return Err(MyError::InvalidPath);
+        }
+        if old_path == new_path
+            || snapshot.file_exists(new_path)?
+            || snapshot.directory_exists(new_path)?
+        {
+            return Err(MyError::PathAlreadyExists);
+        }
+
+        let mut updated_files = Vec::new();
+        for path in all_files(&snapshot)? {
+            let path: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+            let Some((file_name, dir)) = path.split_last() else {
+                continue;
+            };
+            if !file_name
+                .rsplit_once('.')
+                .is_some_and(|(_, file_ext)| is_page(file_ext))
+            {
+                continue;
+            }
+            let Ok(bytes) = snapshot.read_raw_file(&path) else {
+                continue;
+            };
+            let Ok(src) = str::from_utf8(&bytes) else {
+                continue;
+            };
+            let (dir_before, dir_after, target) = if path == old_path {
+                (old_dir, new_dir, new_path)
+            } else {
+                (dir, dir, path.as_slice())
+            };
+            if let Some(content) =
+                rewrite_for_rename(src, dir_before, dir_after, old_path, new_path)
+            {
+                updated_files.push(UpdatedFile {
+                    path: target.iter().map(|s| s.to_string()).collect(),
+                    content,
+                });
+            }
+        }
+
+        self.0.repository.rename_file(
+            old_path,
+            new_path,
+            message,
+            &updated_files,
+            snapshot.commit_id(),
+            self.0.settings.author(),
+        )?;
+
+        let mut changed_paths = vec![old_path.to_vec(), new_path.to_vec()];
+        changed_paths.extend(updated_files.iter().map(|f| f.path()));
+        let changed_paths: Vec<&[&str]> = changed_paths.iter().map(|p| p.as_slice()).collect();
+        self.queue_index_update(&changed_paths);
+        self.committed();
+        Ok(())
+    }
+
+    pub fn delete_file(&self, file_path: &[&str], message: &str) -> Result<(), MyError> {
+        if !self.file_exists(file_path)? {
+            return Err(MyError::InvalidPath);
+        }
+        self.0
+            .repository
+            .delete_file(file_path, message, self.0.settings.author())?;
+        self.queue_index_update(&[file_path]);
+        self.committed();
+        Ok(())
+    }
+
+    /// Pulls from and pushes to the remote configured in `smeagol.toml`, then reindexes the
+    /// files that changed.
+    pub fn sync(&self) -> Result<SyncResult, MyError> {
+        let remote = self
+            .0
+            .settings
+            .sync()
+            .ok_or(MyError::SyncNotConfigured)?
+            .remote();
+        let _syncing = self.0.sync_lock.lock().unwrap();
+        let result = self
+            .0
+            .repository
+            .sync(remote, self.0.settings.author())
+            .inspect(|result| {
+                let paths = split_paths(&result.changed_files);
+                let paths: Vec<&[&str]> = paths.iter().map(|p| p.as_slice()).collect();
+                self.queue_index_update(&paths);
+            });
+        *self.0.last_sync.lock().unwrap() = Some(SyncStatus {
+            time: OffsetDateTime::now_utc(),
+            result: result.as_ref().cloned().map_err(|err| err.to_string()),
+        });
+        result
+    }
+
+    pub fn last_sync(&self) -> Option<SyncStatus> {
+        self.0.last_sync.lock().unwrap().clone()
+    }
+
+    /// Starts syncing in the background at the interval set in `smeagol.toml`, if any.
+    pub fn start_sync_timer(&self) {
+        let Some(interval) = self.0.settings.sync().and_then(|sync| sync.interval()) else {
+            return;
+        };
+        let wiki = self.clone();
+        std::thread::spawn(move || loop {
+            if let Err(err) = wiki.sync() {
+                eprintln!("Failed to sync: {}", err);
+            }
+            std::thread::sleep(interval);
+        });
+    }
+
+    /// Called after committing a change made through the wiki.
+    fn committed(&self) {
+        if !self
+            .0
+            .settings
+            .sync()
+            .is_some_and(|sync| sync.push_on_commit())
+        {
+            return;
+        }
+        let wiki = self.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = wiki.sync() {
+                eprintln!("Failed to sync: {}", err);
+            }
+        });
+    }
+
+    /// Reindexes the given files in the background, together with any other changes made
+    /// around the same time.
+    fn queue_index_update(&self, paths: &[&[&str]]) {
+        let paths = paths.iter().map(|path| path.join("/")).collect();
+        if self.0.index_updates.send(paths).is_err() {
+            eprintln!("Failed to queue files for reindexing.");
+        }
+    }
+
+    /// Brings the search index up to date with the repository for the given files, removing
+    /// files that no longer exist. Files changed by commits made since the index was last
+    /// updated are reindexed as well.
+    fn update_index(&self, paths: &[&[&str]]) -> Result<(), MyError> {
+        if let Some(indexing) = self.0.indexing.lock().unwrap().as_mut() {
+            indexing
+                .pending
+                .extend(paths.iter().map(|path| path.join("/")));
+            return Ok(());
+        }
+        let mut writer = self.0.index_writer.lock().unwrap();
+        let current = self.index_state();
+        let missed = stored_index_state(&self.0.index)?
+            .and_then(|stored| files_changed_since(&self.0.repository, &stored, &current))
+            .unwrap_or_default();
+        let missed = split_paths(&missed);
+        let mut paths = paths.to_vec();
+        for path in &missed {
+            if !paths.contains(&path.as_slice()) {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let search_fields = SearchFields::from_schema(&self.0.index.schema());
+        let snapshot = self.snapshot()?;
+        for path in paths {
+            reindex_file(
+                &self.0.settings,
+                &writer,
+                &search_fields,
+                snapshot.link_targets(),
+                path,
+                self.0.repository.read_file(path).ok().as_deref(),
+            );
+        }
+        self.commit_index(&mut writer, &current)
+    }
+
+    /// Commits the changes made with the index writer, recording that the index now matches
+    /// `state`, and makes them visible to searches.
+    fn commit_index(
+        &self,
+        index_writer: &mut IndexWriter,
+        state: &IndexState,
     ) -> Result<(), MyError> {
-        self.0.repository.write_file(file_path, message, content)?;
-
-        if let Some((file_stem, file_ext)) = file_path.last().unwrap().rsplit_once('.') {
-            if is_page(file_ext) {
-                let mut writer = self.0.index.writer(INDEXING_HEAP_SIZE)?;
-                let search_fields = SearchFields::from_schema(&self.0.index.schema());
-                let page = get_raw_page(file_stem, file_ext, content.as_bytes(), &self.0.settings)?
-                    .unwrap();
-                index_file(file_path, &search_fields, page, &mut writer);
-                writer.commit()?;
+        let mut prepared = index_writer.prepare_commit()?;
+        prepared.set_payload(&state.to_payload());
+        prepared.commit()?;
+        self.0.index_reader.reload()?;
+        Ok(())
+    }
+
+    /// Brings the search index up to date with the repository. Only the files changed since
+    /// the index was last updated are reindexed if possible, and everything otherwise.
+    ///
+    /// Searches made in the meantime use the index as it was, and changes made through the wiki
+    /// are reindexed once this is done.
+    pub fn build_index(&self) -> Result<(), MyError> {
+        let result = self.build_index_inner();
+        let pending = self
+            .0
+            .indexing
+            .lock()
+            .unwrap()
+            .take()
+            .map(|indexing| indexing.pending)
+            .unwrap_or_default();
+        result?;
+        let pending = split_paths(&pending);
+        let pending: Vec<&[&str]> = pending.iter().map(|p| p.as_slice()).collect();
+        self.update_index(&pending)
+    }
+
+    fn build_index_inner(&self) -> Result<(), MyError> {
+        let mut index_writer = self.0.index_writer.lock().unwrap();
+        let current = self.index_state();
+        let changed_files = stored_index_state(&self.0.index)?
+            .and_then(|stored| files_changed_since(&self.0.repository, &stored, &current));
+        if changed_files.as_ref().is_some_and(|files| files.is_empty()) {
+            return Ok(());
+        }
+        match changed_files {
+            Some(files) => {
+                println!(
+                    "Indexing {} files changed since the wiki was last loaded.",
+                    files.len()
+                );
+                self.index_files(&index_writer, &files)?;
+            }
+            None => {
+                println!("Indexing files, this can take a while if there are a lot.");
+                self.index_all_pages(&index_writer)?;
             }
         }
+        self.commit_index(&mut index_writer, &current)?;
+        println!("Finished indexing.");
+        Ok(())
+    }
 
+    /// Reindexes files in parallel, keeping track of the progress while building the index.
+    fn index_files(&self, index_writer: &IndexWriter, files: &[String]) -> Result<(), MyError> {
+        let search_fields = SearchFields::from_schema(&self.0.index.schema());
+        let snapshot = self.snapshot()?;
+        let wiki_link_targets = snapshot.link_targets();
+        let indexed = AtomicUsize::new(0);
+        self.set_indexing_progress(0, files.len());
+        files.par_iter().for_each(|path| {
+            let path: Vec<&str> = path.split('/').collect();
+            let content = self.0.repository.read_file(&path).ok();
+            reindex_file(
+                &self.0.settings,
+                index_writer,
+                &search_fields,
+                wiki_link_targets,
+                &path,
+                content.as_deref(),
+            );
+            let indexed = indexed.fetch_add(1, Ordering::Relaxed) + 1;
+            self.set_indexing_progress(indexed, files.len());
+        });
         Ok(())
     }
 
+    /// Replaces everything in the index with every page. The files are listed in one pass and
+    /// then read and parsed in parallel, each thread reading with its own handle to the
+    /// repository. The same listing is used to resolve wiki links.
+    fn index_all_pages(&self, index_writer: &IndexWriter) -> Result<(), MyError> {
+        let files = self.0.repository.list_files()?;
+        let wiki_link_targets = WikiLinkTargets::new(files.iter().map(|file| file.path.as_str()));
+        let pages: Vec<FileEntry> = files
+            .into_iter()
+            .filter(|file| is_page_path(&file.path))
+            .collect();
+        index_writer.delete_all_documents()?;
+        let search_fields = SearchFields::from_schema(&self.0.index.schema());
+        let indexed = AtomicUsize::new(0);
+        self.set_indexing_progress(0, pages.len());
+        pages.par_iter().for_each_init(
+            || self.0.repository.file_reader(),
+            |reader, file| {
+                let content = reader
+                    .as_ref()
+                    .map_err(|err| err.to_string())
+                    .and_then(|reader| reader.read(file).map_err(|err| err.to_string()));
+                let content = match content {
+                    Ok(content) => Some(content),
+                    Err(err) => {
+                        println!("Failed to open file '{}' for indexing: {}", file.path, err);
+                        None
+                    }
+                };
+                let path: Vec<&str> = file.path.split('/').collect();
+                reindex_file(
+                    &self.0.settings,
+                    index_writer,
+                    &search_fields,
+                    &wiki_link_targets,
+                    &path,
+                    content.as_deref(),
+                );
+                let indexed = indexed.fetch_add(1, Ordering::Relaxed) + 1;
+                self.set_indexing_progress(indexed, pages.len());
+            },
+        );
+        Ok(())
+    }
+
+    fn set_indexing_progress(&self, indexed: usize, total: usize) {
+        if let Some(indexing) = self.0.indexing.lock().unwrap().as_mut() {
+            indexing.progress = IndexingProgress { indexed, total };
+        }
+    }
+
+    /// How far along building the index is, or `None` once it is up to date.
+    pub fn indexing_progress(&self) -> Option<IndexingProgress> {
+        self.0
+            .indexing
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|indexing| indexing.progress)
+    }
+
+    /// Builds the search index in the background.
+    pub fn start_indexing(&self) {
+        let wiki = self.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = wiki.build_index() {
+                eprintln!("Failed to build the search index: {}", err);
+            }
+        });
+    }
+
+    /// Starts keeping the search index up to date with changes made outside of the wiki.
+    pub fn start_watcher(&self) {
+        let wiki = self.clone();
+        let result = self.0.repository.watch(Box::new(move |changed| {
+            let paths = split_paths(&changed);
+            let paths: Vec<&[&str]> = paths.iter().map(|p| p.as_slice()).collect();
+            wiki.queue_index_update(&paths);
+        }));
+        if let Err(err) = result {
+            eprintln!("Not watching for changes made outside of the wiki: {}", err);
+        }
+    }
+
+    fn index_state(&self) -> IndexState {
+        IndexState::current(&self.0.settings, &self.0.repository)
+    }
+
+    /// Commits the contents the file had in `revision` on top of the current version.
+    pub fn restore_file(&self, file_path: &[&str], revision: &str) -> Result<(), MyError> {
+        let commit = self.0.repository.get_commit(revision)?;
+        let old_content = self
+            .0
+            .repository
+            .read_file_at_revision(file_path, &commit.id)?;
+        if let Ok(current_content) = self.0.repository.read_file(file_path) {
+            if current_content == old_content {
+                return Ok(());
+            }
+        }
+        let message = format!("Revert {} to {}", file_path.join("/"), commit.short_id);
+        self.write_file(file_path, &message, &old_content, None)
+    }
+
+    pub fn history(&self, file_path: &[&str]) -> Result<Vec<CommitInfo>, MyError> {
+        self.0.repository.history(file_path)
+    }
+
     pub fn directory_exists(&self, path: &[&str]) -> Result<bool, MyError> {
         self.0.repository.directory_exists(path)
     }
@@ -274,27 +1295,25 @@ impl Wiki {
         self.0.repository.file_exists(path)
     }
 
-    pub fn enumerate_files(&self, directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
-        self.0.repository.enumerate_files(directory)
-    }
-
     pub fn search(
         &self,
         query: &str,
         num_results: usize,
         offset: Option<usize>,
     ) -> Result<Vec<SearchResult>, MyError> {
-        let reader = self
-            .0
-            .index
-            .reader_builder()
-            .reload_policy(ReloadPolicy::OnCommitWithDelay)
-            .try_into()?;
-
-        let searcher = reader.searcher();
+        let searcher = self.0.index_reader.searcher();
         let fields = SearchFields::from_schema(&self.0.index.schema());
-        let query_parser =
-            QueryParser::for_index(&self.0.index, vec![fields.path, fields.title, fields.body]);
+        let query_parser = QueryParser::for_index(
+            &self.0.index,
+            vec![
+                fields.path,
+                fields.title,
+                fields.aliases,
+                fields.tags,
+                fields.description,
+                fields.body,
+            ],
+        );
 
         let query = query_parser.parse_query(query)?;
 
@@ -339,13 +1358,40 @@ impl Wiki {
             })
             .collect())
     }
+
+    /// The pages that link to or transclude the file at `file_path`, sorted by title. Reads the
+    /// search index, so changes show up once they are reindexed.
+    pub fn backlinks(&self, file_path: &[&str]) -> Result<Vec<Backlink>, MyError> {
+        let searcher = self.0.index_reader.searcher();
+        let fields = SearchFields::from_schema(&self.0.index.schema());
+        let query = TermQuery::new(
+            Term::from_field_text(fields.links, &path_to_url(file_path)),
+            IndexRecordOption::Basic,
+        );
+        let mut backlinks: Vec<Backlink> = searcher
+            .search(&query, &DocSetCollector)?
+            .into_iter()
+            .filter_map(|doc_address| {
+                let doc = searcher.doc::<TantivyDocument>(doc_address).ok()?;
+                let title = doc.get_first(fields.title)?.as_str()?.to_owned();
+                let path = doc.get_first(fields.path)?.as_str()?.to_owned();
+                Some(Backlink { title, path })
+            })
+            .collect();
+        backlinks.sort_by(|a, b| (&a.title, &a.path).cmp(&(&b.title, &b.path)));
+        Ok(backlinks)
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::repository::ChangeCallback;
+    use crate::repository::FileReader;
+    use crate::repository::LiveSnapshot;
     use crate::repository::Repository;
     use std::collections::HashMap;
+    use tempfile::TempDir;
 
     struct FakeRepo {
         files: HashMap<String, String>,
@@ -355,6 +1401,48 @@ mod tests {
         fn capabilities(&self) -> RepositoryCapability {
             RepositoryCapability::empty()
         }
+        fn history(&self, _file_path: &[&str]) -> Result<Vec<CommitInfo>, MyError> {
+            unimplemented!();
+        }
+        fn read_file_at_revision(
+            &self,
+            _file_path: &[&str],
+            _revision: &str,
+        ) -> Result<Vec<u8>, MyError> {
+            unimplemented!();
+        }
+        fn get_commit(&self, _revision: &str) -> Result<CommitInfo, MyError> {
+            unimplemented!();
+        }
+        fn head_commit(&self) -> Result<CommitInfo, MyError> {
+            Err(MyError::UnsupportedOperation)
+        }
+        fn branches(&self) -> Result<Vec<String>, MyError> {
+            unimplemented!();
+        }
+        fn current_branch(&self) -> Option<String> {
+            None
+        }
+        fn set_branch(&self, _name: &str) -> Result<(), MyError> {
+            unimplemented!();
+        }
+        fn sync(&self, _remote: &str, _author: Option<&Author>) -> Result<SyncResult, MyError> {
+            unimplemented!();
+        }
+        fn changed_files(&self, _from: &str, _to: &str) -> Result<Vec<String>, MyError> {
+            unimplemented!();
+        }
+        fn blame(&self, _file_path: &[&str]) -> Result<Vec<BlameHunk>, MyError> {
+            unimplemented!();
+        }
+        fn diff_file(
+            &self,
+            _file_path: &[&str],
+            _from: &str,
+            _to: &str,
+        ) -> Result<Vec<DiffHunk>, MyError> {
+            unimplemented!();
+        }
         fn read_file(&self, file_path: &[&str]) -> Result<Vec<u8>, MyError> {
             let key = file_path.join("/");
             if let Some(f) = self.files.get(&key) {
@@ -367,7 +1455,37 @@ mod tests {
             &self,
             _file_path: &[&str],
             _message: &str,
-            _content: &str,
+            _content: &[u8],
+            _author: Option<&Author>,
+        ) -> Result<(), MyError> {
+            unimplemented!();
+        }
+        fn write_file_on(
+            &self,
+            _file_path: &[&str],
+            _message: &str,
+            _content: &[u8],
+            _parent: &str,
+            _author: Option<&Author>,
+        ) -> Result<(), MyError> {
+            unimplemented!();
+        }
+        fn rename_file(
+            &self,
+            _old_path: &[&str],
+            _new_path: &[&str],
+            _message: &str,
+            _updated_files: &[UpdatedFile],
+            _parent: Option<&str>,
+            _author: Option<&Author>,
+        ) -> Result<(), MyError> {
+            unimplemented!();
+        }
+        fn delete_file(
+            &self,
+            _file_path: &[&str],
+            _message: &str,
+            _author: Option<&Author>,
         ) -> Result<(), MyError> {
             unimplemented!();
         }
@@ -381,13 +1499,33 @@ mod tests {
         fn enumerate_files(&self, _directory: &[&str]) -> Result<Vec<RepositoryItem>, MyError> {
             Ok(vec![])
         }
+        fn list_files(&self) -> Result<Vec<FileEntry>, MyError> {
+            Ok(vec![])
+        }
+        fn file_reader(&self) -> Result<Box<dyn FileReader>, MyError> {
+            Err(MyError::UnsupportedOperation)
+        }
+        fn watch(&self, _on_change: ChangeCallback) -> Result<(), MyError> {
+            unimplemented!();
+        }
+        fn snapshot(&self) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+            Ok(Box::new(LiveSnapshot(self)))
+        }
+        fn snapshot_at(
+            &self,
+            _revision: &str,
+        ) -> Result<Box<dyn RepositorySnapshot + '_>, MyError> {
+            unimplemented!();
+        }
     }
 
     fn create_fake_wiki(files: HashMap<String, String>) -> Wiki {
         let settings = Settings::new("index.md", false);
         let repo = FakeRepo { files };
         let repo_box = RepoBox(Box::new(repo));
-        Wiki::new(settings, repo_box).unwrap()
+        let wiki = Wiki::new(settings, repo_box).unwrap();
+        wiki.build_index().unwrap();
+        wiki
     }
 
     #[test]
@@ -396,7 +1534,8 @@ mod tests {
             "stuff.md".to_owned(),
             "things".to_owned(),
         )]));
-        let result = String::from_utf8(wiki.read_file(&["stuff.md"]).unwrap()).unwrap();
+        let result =
+            String::from_utf8(wiki.snapshot().unwrap().read_file(&["stuff.md"]).unwrap()).unwrap();
         assert_eq!(result, "things");
     }
 
@@ -409,7 +1548,8 @@ mod tests {
             ),
             ("more_stuff.md".to_owned(), "much more stuff".to_owned()),
         ]));
-        let result = String::from_utf8(wiki.read_file(&["stuff.md"]).unwrap()).unwrap();
+        let result =
+            String::from_utf8(wiki.snapshot().unwrap().read_file(&["stuff.md"]).unwrap()).unwrap();
         assert_eq!(result, "things\nmuch more stuff\nmore things");
     }
 
@@ -422,7 +1562,8 @@ mod tests {
             ),
             ("more_stuff.md".to_owned(), "much more stuff".to_owned()),
         ]));
-        let result = String::from_utf8(wiki.read_file(&["stuff.md"]).unwrap()).unwrap();
+        let result =
+            String::from_utf8(wiki.snapshot().unwrap().read_file(&["stuff.md"]).unwrap()).unwrap();
         assert_eq!(result, "things {{more_stuff.md}} more things");
     }
 
@@ -432,7 +1573,469 @@ mod tests {
             "stuff.md".to_owned(),
             "things\n{{does-not-exist.md}}\nmore things".to_owned(),
         )]));
-        let result = String::from_utf8(wiki.read_file(&["stuff.md"]).unwrap()).unwrap();
+        let result =
+            String::from_utf8(wiki.snapshot().unwrap().read_file(&["stuff.md"]).unwrap()).unwrap();
         assert_eq!(result, "things\n**read error**\nmore things");
     }
+
+    #[test]
+    fn test_index_state_payload() {
+        let state = IndexState {
+            version: INDEX_FORMAT_VERSION,
+            h1_title: true,
+            commit: Some("0123abcd".to_owned()),
+        };
+        assert_eq!(
+            Some(&state),
+            IndexState::from_payload(&state.to_payload()).as_ref()
+        );
+        let state = IndexState {
+            commit: None,
+            ..state
+        };
+        assert_eq!(
+            Some(&state),
+            IndexState::from_payload(&state.to_payload()).as_ref()
+        );
+        assert_eq!(None, IndexState::from_payload("garbage"));
+    }
+
+    /// Writes files to the working directory of the Git repository in `dir` and commits them.
+    pub(crate) fn commit_files(dir: &Path, files: &[(&str, &str)]) -> String {
+        let repo = git2::Repository::open(dir).unwrap();
+        let mut index = repo.index().unwrap();
+        for (name, content) in files {
+            let path = repo.workdir().unwrap().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+            index.add_path(Path::new(name)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "test",
+            &tree,
+            &parents,
+        )
+        .unwrap()
+        .to_string()
+    }
+
+    /// Creates a Git repository with `files` committed to it, and a wiki serving it. The
+    /// repository is removed when the returned directory is dropped.
+    fn test_wiki_with(settings: Settings, files: &[(&str, &str)]) -> (TempDir, Wiki) {
+        let wiki_dir = tempfile::tempdir().unwrap();
+        let mut config = git2::Repository::init(wiki_dir.path())
+            .unwrap()
+            .config()
+            .unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        commit_files(wiki_dir.path(), files);
+        let repo = crate::repository::create_repository(false, wiki_dir.path().to_owned());
+        let wiki = Wiki::new(settings, repo.unwrap()).unwrap();
+        (wiki_dir, wiki)
+    }
+
+    pub(crate) fn test_wiki(files: &[(&str, &str)]) -> (TempDir, Wiki) {
+        test_wiki_with(Settings::new("index.md", false), files)
+    }
+
+    #[test]
+    fn test_stored_index_catches_up_with_new_commits() {
+        let index_dir = tempfile::tempdir().unwrap();
+        let settings = || Settings::new("index.md", false).with_index_dir(index_dir.path());
+        let (wiki_dir, wiki) = test_wiki_with(settings(), &[("a.md", "apples")]);
+        wiki.build_index().unwrap();
+        assert_eq!(1, wiki.search("apples", 10, None).unwrap().len());
+        drop(wiki);
+
+        let head = commit_files(
+            wiki_dir.path(),
+            &[("a.md", "bananas"), ("b.md", "cherries")],
+        );
+        let repo = crate::repository::create_repository(false, wiki_dir.path().to_owned());
+        let wiki = Wiki::new(settings(), repo.unwrap()).unwrap();
+        wiki.build_index().unwrap();
+        assert_eq!(0, wiki.search("apples", 10, None).unwrap().len());
+        assert_eq!(1, wiki.search("bananas", 10, None).unwrap().len());
+        assert_eq!(1, wiki.search("cherries", 10, None).unwrap().len());
+        let payload = wiki.0.index.load_metas().unwrap().payload.unwrap();
+        assert_eq!(
+            Some(head),
+            IndexState::from_payload(&payload).unwrap().commit
+        );
+    }
+
+    #[test]
+    fn test_update_index_picks_up_outside_commits() {
+        let (wiki_dir, wiki) = test_wiki(&[("a.md", "apples")]);
+        wiki.build_index().unwrap();
+
+        commit_files(wiki_dir.path(), &[("b.md", "bananas")]);
+        wiki.update_index(&[]).unwrap();
+        assert_eq!(1, wiki.search("bananas", 10, None).unwrap().len());
+    }
+
+    #[test]
+    fn test_changes_before_indexing_are_indexed_afterwards() {
+        let (wiki_dir, wiki) = test_wiki(&[("a.md", "apples")]);
+        assert!(wiki.indexing_progress().is_some());
+
+        // Changes made before the index is built wait for it instead of being indexed on their own.
+        commit_files(wiki_dir.path(), &[("b.md", "bananas")]);
+        wiki.update_index(&[&["b.md"]]).unwrap();
+        assert_eq!(0, wiki.search("bananas", 10, None).unwrap().len());
+
+        wiki.build_index().unwrap();
+        assert!(wiki.indexing_progress().is_none());
+        assert_eq!(1, wiki.search("apples", 10, None).unwrap().len());
+        assert_eq!(1, wiki.search("bananas", 10, None).unwrap().len());
+    }
+
+    #[test]
+    fn test_saves_are_indexed_in_the_background() {
+        let (_wiki_dir, wiki) = test_wiki(&[("a.md", "apples")]);
+        wiki.build_index().unwrap();
+
+        let author = test_author();
+        wiki.write_file(&["b.md"], "Add b", b"bananas", Some(&author))
+            .unwrap();
+        wiki.write_file(&["c.md"], "Add c", b"cherries", Some(&author))
+            .unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while wiki.search("bananas OR cherries", 10, None).unwrap().len() < 2 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_changed_pages_replace_their_search_results() {
+        let (wiki_dir, wiki) = test_wiki(&[("a b.md", "apples"), ("c.md", "cherries")]);
+        wiki.build_index().unwrap();
+
+        commit_files(wiki_dir.path(), &[("a b.md", "bananas")]);
+        wiki.update_index(&[&["a b.md"]]).unwrap();
+        assert_eq!(0, wiki.search("apples", 10, None).unwrap().len());
+        assert_eq!(1, wiki.search("bananas", 10, None).unwrap().len());
+
+        let repo = git2::Repository::open(wiki_dir.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("c.md")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "test",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+        wiki.update_index(&[&["c.md"]]).unwrap();
+        assert_eq!(0, wiki.search("cherries", 10, None).unwrap().len());
+    }
+
+    #[test]
+    fn test_switching_branches_reindexes_in_the_background() {
+        let (wiki_dir, wiki) = test_wiki(&[("a.md", "apples")]);
+        wiki.build_index().unwrap();
+        let repo = git2::Repository::open(wiki_dir.path()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("docs", &head, false).unwrap();
+        commit_files(wiki_dir.path(), &[("b.md", "bananas")]);
+        wiki.update_index(&[]).unwrap();
+        assert_eq!(1, wiki.search("bananas", 10, None).unwrap().len());
+
+        wiki.switch_branch("docs").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !wiki.search("bananas", 10, None).unwrap().is_empty() {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(1, wiki.search("apples", 10, None).unwrap().len());
+    }
+
+    #[test]
+    fn test_transclusions_follow_pages_renamed_into_directories() {
+        let (_wiki_dir, wiki) = test_wiki(&[("page.md", "{{inc.md}}\n"), ("inc.md", "one")]);
+        wiki.rename_file(&["inc.md"], &["dir", "inc.md"], "Move")
+            .unwrap();
+
+        let snapshot = wiki.snapshot().unwrap();
+        assert_eq!(
+            "{{dir/inc.md}}\n",
+            str::from_utf8(&snapshot.read_raw_file(&["page.md"]).unwrap()).unwrap()
+        );
+        let page = wiki.render_page(&snapshot, &["page.md"]).unwrap().unwrap();
+        assert_eq!("<p>one</p>\n", page.body);
+    }
+
+    #[test]
+    fn test_rendered_pages_follow_transclusions() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "{{inc.md}}\n"), ("inc.md", "one")]);
+        let render = || {
+            let snapshot = wiki.snapshot().unwrap();
+            wiki.render_page(&snapshot, &["page.md"])
+                .unwrap()
+                .unwrap()
+                .body
+        };
+
+        assert_eq!("<p>one</p>\n", render());
+        assert_eq!("<p>one</p>\n", render());
+        assert_eq!(1, wiki.0.page_cache.lock().unwrap().len());
+
+        commit_files(wiki_dir.path(), &[("inc.md", "two")]);
+        assert_eq!("<p>two</p>\n", render());
+        assert_eq!(1, wiki.0.page_cache.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_links_to_missing_pages() {
+        let (wiki_dir, wiki) = test_wiki(&[("dir/page.md", "[other](../other.md)\n")]);
+        let render = || {
+            let snapshot = wiki.snapshot().unwrap();
+            wiki.render_page(&snapshot, &["dir", "page.md"])
+                .unwrap()
+                .unwrap()
+                .body
+        };
+
+        assert_eq!(
+            "<p><a class=\"missing-page\" href=\"/new/other.md\">other</a></p>\n",
+            render()
+        );
+
+        commit_files(wiki_dir.path(), &[("other.md", "text")]);
+        assert_eq!("<p><a href=\"../other.md\">other</a></p>\n", render());
+    }
+
+    #[test]
+    fn test_history() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\n")]);
+        let first = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        let second = commit_files(wiki_dir.path(), &[("page.md", "two\n")]);
+        commit_files(wiki_dir.path(), &[("other.md", "other\n")]);
+
+        let history = wiki.history(&["page.md"]).unwrap();
+        let ids: Vec<&str> = history.iter().map(|commit| commit.id.as_str()).collect();
+        assert_eq!(vec![second.as_str(), first.as_str()], ids);
+        assert_eq!("Test", history[0].author_name);
+        assert!(wiki.history(&["missing.md"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restore_file() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\n")]);
+        let first = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        commit_files(wiki_dir.path(), &[("page.md", "two\n")]);
+
+        wiki.restore_file(&["page.md"], &first).unwrap();
+        assert_eq!("one\n", read_page(&wiki));
+        let history = wiki.history(&["page.md"]).unwrap();
+        assert_eq!(3, history.len());
+        assert!(history[0].summary.starts_with("Revert page.md to "));
+
+        // Restoring the current contents again commits nothing.
+        wiki.restore_file(&["page.md"], &first).unwrap();
+        assert_eq!(3, wiki.history(&["page.md"]).unwrap().len());
+        assert!(matches!(
+            wiki.restore_file(&["page.md"], "missing"),
+            Err(MyError::RevisionNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_delete_file() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\n"), ("dir/other.md", "other\n")]);
+
+        wiki.delete_file(&["dir", "other.md"], "Delete other")
+            .unwrap();
+        assert!(!wiki.file_exists(&["dir", "other.md"]).unwrap());
+        assert!(!wiki.directory_exists(&["dir"]).unwrap());
+        assert!(wiki.file_exists(&["page.md"]).unwrap());
+        assert!(!wiki_dir.path().join("dir/other.md").exists());
+        let history = wiki.history(&["dir", "other.md"]).unwrap();
+        assert_eq!("Delete other", history[0].summary);
+
+        assert!(matches!(
+            wiki.delete_file(&["dir", "other.md"], "Delete again"),
+            Err(MyError::InvalidPath)
+        ));
+    }
+
+    #[test]
+    fn test_blame() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\ntwo\n")]);
+        let first = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        let second = commit_files(wiki_dir.path(), &[("page.md", "one\nTWO\nthree\n")]);
+
+        let hunks = wiki.blame(&["page.md"]).unwrap();
+        let lines: Vec<(&str, usize, &str)> = hunks
+            .iter()
+            .flat_map(|hunk| {
+                hunk.lines
+                    .iter()
+                    .map(|(number, text)| (hunk.commit.id.as_str(), *number, text.as_str()))
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (first.as_str(), 1, "one"),
+                (second.as_str(), 2, "TWO"),
+                (second.as_str(), 3, "three"),
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn test_links_in_old_revisions() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "[other](other.md) [[Other]]")]);
+        let old = wiki.snapshot().unwrap().commit_id().unwrap().to_owned();
+        commit_files(wiki_dir.path(), &[("other.md", "text")]);
+        let markdown = PageLink::Markdown("other.md".to_owned());
+        let wiki_link = PageLink::Wiki("Other".to_owned());
+
+        let snapshot = wiki.snapshot_at(&old).unwrap();
+        assert!(!snapshot.resolve_link(&[], &markdown).unwrap().exists);
+        assert!(!snapshot.resolve_link(&[], &wiki_link).unwrap().exists);
+
+        let snapshot = wiki.snapshot().unwrap();
+        assert_eq!(None, snapshot.resolve_link(&[], &markdown));
+        assert!(snapshot.resolve_link(&[], &wiki_link).unwrap().exists);
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let (wiki_dir, wiki) = test_wiki_with(
+            Settings::new("index.md", true),
+            &[
+                ("target.md", "[self](target.md)"),
+                ("docs/wiki.md", "# Wiki\n[[Target]]"),
+                ("markdown.md", "# Markdown\n[there](target.md#section)"),
+                ("include.md", "# Include\n{{target.md}}\n"),
+                ("other.md", "[[Elsewhere]]"),
+            ],
+        );
+        wiki.build_index().unwrap();
+        let backlinks = |path: &[&str]| -> Vec<String> {
+            let backlinks = wiki.backlinks(path).unwrap();
+            backlinks.into_iter().map(|link| link.path).collect()
+        };
+
+        assert_eq!(
+            vec!["/include.md", "/markdown.md", "/docs/wiki.md"],
+            backlinks(&["target.md"])
+        );
+        assert_eq!(vec!["/other.md"], backlinks(&["Elsewhere.md"]));
+
+        commit_files(wiki_dir.path(), &[("markdown.md", "no links")]);
+        wiki.update_index(&[&["markdown.md"]]).unwrap();
+        assert_eq!(
+            vec!["/include.md", "/docs/wiki.md"],
+            backlinks(&["target.md"])
+        );
+    }
+
+    fn test_author() -> Author {
+        Author {
+            name: "Test".to_owned(),
+            email: "test@example.com".to_owned(),
+        }
+    }
+
+    fn read_page(wiki: &Wiki) -> String {
+        let bytes = wiki
+            .snapshot()
+            .unwrap()
+            .read_raw_file(&["page.md"])
+            .unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_save_edit_without_changes_since_base() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\n")]);
+        let base = commit_files(wiki_dir.path(), &[("other.md", "other")]);
+
+        let result = wiki.save_edit(
+            &["page.md"],
+            "Edit",
+            "two\n",
+            Some(&base),
+            Some(&test_author()),
+        );
+        assert!(matches!(result, Ok(EditResult::Saved)));
+        assert_eq!("two\n", read_page(&wiki));
+    }
+
+    #[test]
+    fn test_save_edit_merges_changes_made_since_base() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\ntwo\nthree\n")]);
+        let base = wiki.0.repository.head_commit().unwrap().id;
+        commit_files(wiki_dir.path(), &[("page.md", "ONE\ntwo\nthree\n")]);
+
+        let result = wiki.save_edit(
+            &["page.md"],
+            "Edit",
+            "one\ntwo\nTHREE\n",
+            Some(&base),
+            Some(&test_author()),
+        );
+        assert!(matches!(result, Ok(EditResult::Saved)));
+        assert_eq!("ONE\ntwo\nTHREE\n", read_page(&wiki));
+    }
+
+    #[test]
+    fn test_save_edit_merges_crlf_edits() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\ntwo\nthree\n")]);
+        let base = wiki.0.repository.head_commit().unwrap().id;
+        commit_files(wiki_dir.path(), &[("page.md", "ONE\ntwo\nthree\n")]);
+
+        let result = wiki.save_edit(
+            &["page.md"],
+            "Edit",
+            "one\r\ntwo\r\nTHREE\r\n",
+            Some(&base),
+            Some(&test_author()),
+        );
+        assert!(matches!(result, Ok(EditResult::Saved)));
+        assert_eq!("ONE\ntwo\nTHREE\n", read_page(&wiki));
+    }
+
+    #[test]
+    fn test_save_edit_conflict() {
+        let (wiki_dir, wiki) = test_wiki(&[("page.md", "one\ntwo\nthree\n")]);
+        let base = wiki.0.repository.head_commit().unwrap().id;
+        let head = commit_files(wiki_dir.path(), &[("page.md", "one\ntheirs\nthree\n")]);
+
+        let result = wiki.save_edit(
+            &["page.md"],
+            "Edit",
+            "one\nmine\nthree\n",
+            Some(&base),
+            Some(&test_author()),
+        );
+        let Ok(EditResult::Conflict { content, head: at }) = result else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(
+            "one\n<<<<<<< your changes\nmine\n=======\ntheirs\n>>>>>>> current version\nthree\n",
+            content
+        );
+        assert_eq!(head, at.id);
+        assert_eq!("one\ntheirs\nthree\n", read_page(&wiki));
+    }
 }